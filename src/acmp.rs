@@ -0,0 +1,366 @@
+//! # Analog comparators for the KE06Z
+//!
+//! The KE06Z has two analog comparators, each with three external inputs
+//! and an internal 6-bit DAC. Either input of the comparator can be any
+//! of the external pins or the DAC, so a pin can be compared against
+//! another pin (e.g. for zero-cross detection) or against a programmable
+//! threshold (e.g. for an over-current trip).
+//!
+//! The output can be driven on to the ACMPx_OUT pin, polled, or used to
+//! raise an interrupt on a rising edge, falling edge or both.
+//!
+//! The output can also be routed internally (via SIM_SOPT0) to trigger
+//! the ADC, to FTM1 channel 0 input capture (ACMP0 only), or to FTM2
+//! hardware trigger 0 (see `ftm::Ftm::enable_hardware_trigger`). Each
+//! output is also wired to one of FTM2's fault inputs, so an over-current
+//! trip can shut off the PWM outputs with no software in the loop.
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use super::adc::Trigger;
+use super::ftm::FaultInput;
+use super::power;
+use super::registers as reg;
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+/// Selects which comparator we're talking about
+#[derive(PartialEq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum AcmpId {
+    Acmp0,
+    Acmp1,
+}
+
+/// A comparator input
+#[derive(PartialEq, Clone, Copy)]
+pub enum Input {
+    /// External pin ACMPx_IN0
+    In0,
+    /// External pin ACMPx_IN1
+    In1,
+    /// External pin ACMPx_IN2
+    In2,
+    /// The comparator's internal DAC
+    Dac,
+}
+
+/// What the internal DAC divides down
+#[derive(PartialEq, Clone, Copy)]
+pub enum DacReference {
+    /// The internal bandgap reference (about 1.16 V). Using it locks the
+    /// low-voltage detect settings - see `power::enable_bandgap_buffer`.
+    Bandgap,
+    /// The analog supply
+    Vdda,
+}
+
+/// How much hysteresis the comparator has
+#[derive(PartialEq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum Hysteresis {
+    Mv20,
+    Mv30,
+}
+
+/// Which output transitions cause an interrupt
+#[derive(PartialEq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum Edge {
+    Falling,
+    Rising,
+    Both,
+}
+
+/// The ways a request can be wrong
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AcmpError {
+    /// This comparator's output can't be routed there
+    NoRoute,
+}
+
+/// Called from interrupt context when the selected edge occurs, with the
+/// comparator's output at that point.
+pub type AcmpCallback = fn(id: AcmpId, output: bool);
+
+/// Controls an analog comparator
+pub struct Acmp {
+    id: AcmpId,
+    reg: &'static mut reg::AcmpRegisters,
+}
+
+// ****************************************************************************
+//
+// Private Types
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// Public Data
+//
+// ****************************************************************************
+
+/// The largest value the internal DAC takes
+pub const DAC_MAX: u8 = 63;
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+/// Only touched by the interrupt handlers while the comparator
+/// interrupt is enabled.
+static mut CALLBACKS: [Option<AcmpCallback>; 2] = [None, None];
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+/// Represents an analog comparator on the KE06Z
+impl Acmp {
+    /// Create a new Acmp object. The caller is responsible for ensuring
+    /// that only one object exists per comparator. The comparator is
+    /// enabled, with the output pin and interrupt off. If either input is
+    /// `Input::Dac`, call `set_dac` to give it a value.
+    pub fn new(id: AcmpId, positive: Input, negative: Input, hysteresis: Hysteresis) -> Acmp {
+        let mut acmp = Acmp {
+            id: id,
+            reg: get_acmp_registers(id),
+        };
+
+        // Enable the ACMP peripheral
+        reg::get_sim().scgc.modify(|x| {
+            x |
+            match id {
+                AcmpId::Acmp0 => reg::SIM_SCGC_ACMP0,
+                AcmpId::Acmp1 => reg::SIM_SCGC_ACMP1,
+            }
+        });
+
+        // Turn it off while we set it up
+        acmp.reg.cs.write(0);
+        acmp.set_inputs(positive, negative);
+        acmp.set_hysteresis(hysteresis);
+        acmp.reg.cs.modify(|x| x | reg::ACMP_CS_ACE);
+
+        acmp
+    }
+
+    /// Select the comparator inputs. External inputs have their pin's
+    /// digital input disabled; unused ones are handed back to the port.
+    pub fn set_inputs(&mut self, positive: Input, negative: Input) {
+        let pins = input_pin_mask(positive) | input_pin_mask(negative);
+        self.reg.c2.write(reg::acmp_c2_acipe(pins));
+        self.reg.c0.write(reg::acmp_c0_acpsel(input_number(positive)) |
+                          reg::acmp_c0_acnsel(input_number(negative)));
+    }
+
+    /// Turn on the internal DAC. It outputs `reference` * (`value` + 1)
+    /// / 64, where `value` is at most `DAC_MAX`.
+    pub fn set_dac(&mut self, reference: DacReference, value: u8) {
+        let reference = match reference {
+            DacReference::Bandgap => {
+                power::enable_bandgap_buffer();
+                0
+            }
+            DacReference::Vdda => reg::ACMP_C1_DACREF,
+        };
+        self.reg.c1.write(reg::ACMP_C1_DACEN | reference | reg::acmp_c1_dacval(value));
+    }
+
+    /// Turn off the internal DAC.
+    pub fn disable_dac(&mut self) {
+        self.reg.c1.write(0);
+    }
+
+    /// Change the hysteresis.
+    pub fn set_hysteresis(&mut self, hysteresis: Hysteresis) {
+        match hysteresis {
+            Hysteresis::Mv20 => self.reg.cs.modify(|x| x & !reg::ACMP_CS_HYST),
+            Hysteresis::Mv30 => self.reg.cs.modify(|x| x | reg::ACMP_CS_HYST),
+        }
+    }
+
+    /// Drive the comparator output on to the ACMPx_OUT pin.
+    pub fn enable_output_pin(&mut self) {
+        self.reg.cs.modify(|x| x | reg::ACMP_CS_ACOPE);
+    }
+
+    /// Stop driving the ACMPx_OUT pin.
+    pub fn disable_output_pin(&mut self) {
+        self.reg.cs.modify(|x| x & !reg::ACMP_CS_ACOPE);
+    }
+
+    /// Returns true if the positive input is above the negative input.
+    pub fn output(&self) -> bool {
+        (self.reg.cs.read() & reg::ACMP_CS_ACO) != 0
+    }
+
+    /// Select which output transitions set the edge flag. If `callback`
+    /// is given, the comparator interrupt is enabled and `acmp0_isr` or
+    /// `acmp1_isr` (which must be in the vector table and enabled) calls
+    /// it on every such edge. Otherwise, poll `edge_detected`.
+    pub fn enable_interrupt(&mut self, edge: Edge, callback: Option<AcmpCallback>) {
+        let mode = match edge {
+            Edge::Falling => 0,
+            Edge::Rising => 1,
+            Edge::Both => 3,
+        };
+        // Disable the interrupt, and clear any stale flag, while we
+        // change the callback.
+        self.reg.cs.modify(|x| x & !(reg::ACMP_CS_ACIE | reg::ACMP_CS_ACF | reg::ACMP_CS_ACMOD));
+        unsafe {
+            CALLBACKS[self.id as usize] = callback;
+        }
+        let acie = if callback.is_some() {
+            reg::ACMP_CS_ACIE
+        } else {
+            0
+        };
+        self.reg.cs.modify(|x| x | reg::acmp_cs_acmod(mode) | acie);
+    }
+
+    /// Disable the comparator interrupt.
+    pub fn disable_interrupt(&mut self) {
+        self.reg.cs.modify(|x| x & !reg::ACMP_CS_ACIE);
+        unsafe {
+            CALLBACKS[self.id as usize] = None;
+        }
+    }
+
+    /// The ADC hardware trigger which fires on this comparator's output.
+    /// Pass it to `Adc::set_trigger`.
+    pub fn adc_trigger(&self) -> Trigger {
+        match self.id {
+            AcmpId::Acmp0 => Trigger::Acmp0,
+            AcmpId::Acmp1 => Trigger::Acmp1,
+        }
+    }
+
+    /// The FTM2 fault input this comparator's output is wired to. Pass it
+    /// to `Ftm::enable_fault` with `Polarity::ActiveHigh` to turn the
+    /// PWM outputs off while the positive input is above the negative
+    /// input, and `FaultClearing::Automatic` for cycle-by-cycle current
+    /// limiting.
+    pub fn ftm2_fault_input(&self) -> FaultInput {
+        match self.id {
+            AcmpId::Acmp0 => FaultInput::Fault1,
+            AcmpId::Acmp1 => FaultInput::Fault2,
+        }
+    }
+
+    /// Use this comparator's output, instead of the FTM1_CH0 pin, as the
+    /// input to FTM1 channel 0 (e.g. for input capture of zero-crossings).
+    /// Only ACMP0 can do this.
+    pub fn route_to_ftm1_capture(&mut self) -> Result<(), AcmpError> {
+        match self.id {
+            AcmpId::Acmp0 => {
+                reg::get_sim().sopt0.modify(|x| x | reg::SIM_SOPT0_ACIC);
+                Ok(())
+            }
+            AcmpId::Acmp1 => Err(AcmpError::NoRoute),
+        }
+    }
+
+    /// Use this comparator's output as FTM2 hardware trigger 0. FTM2
+    /// must also be set up to act on that trigger, with
+    /// `Ftm::enable_hardware_trigger(HardwareTrigger::Trig0)`.
+    pub fn route_to_ftm2_trigger(&mut self) {
+        match self.id {
+            AcmpId::Acmp0 => reg::get_sim().sopt0.modify(|x| x & !reg::SIM_SOPT0_ACTRG),
+            AcmpId::Acmp1 => reg::get_sim().sopt0.modify(|x| x | reg::SIM_SOPT0_ACTRG),
+        }
+    }
+
+    /// Returns true (and clears the flag) if the selected edge has
+    /// occurred since the last call.
+    pub fn edge_detected(&mut self) -> bool {
+        if (self.reg.cs.read() & reg::ACMP_CS_ACF) != 0 {
+            // ACF is cleared by writing a zero
+            self.reg.cs.modify(|x| x & !reg::ACMP_CS_ACF);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Give FTM1 channel 0 back its FTM1_CH0 pin, undoing
+/// `route_to_ftm1_capture`.
+pub fn release_ftm1_capture() {
+    reg::get_sim().sopt0.modify(|x| x & !reg::SIM_SOPT0_ACIC);
+}
+
+/// Called when ACMP 0 interrupt fires
+pub unsafe extern "C" fn acmp0_isr() {
+    service(AcmpId::Acmp0);
+}
+
+/// Called when ACMP 1 interrupt fires
+pub unsafe extern "C" fn acmp1_isr() {
+    service(AcmpId::Acmp1);
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+fn get_acmp_registers(id: AcmpId) -> &'static mut reg::AcmpRegisters {
+    match id {
+        AcmpId::Acmp0 => reg::get_acmp0(),
+        AcmpId::Acmp1 => reg::get_acmp1(),
+    }
+}
+
+/// The ACPSEL/ACNSEL value for an input
+fn input_number(input: Input) -> u8 {
+    match input {
+        Input::In0 => 0,
+        Input::In1 => 1,
+        Input::In2 => 2,
+        Input::Dac => 3,
+    }
+}
+
+/// The ACIPE bit for an input, if it's a pin
+fn input_pin_mask(input: Input) -> u8 {
+    match input {
+        Input::Dac => 0,
+        _ => 1 << input_number(input),
+    }
+}
+
+/// Clear the edge flag and pass the output to the callback.
+unsafe fn service(id: AcmpId) {
+    let acmp = get_acmp_registers(id);
+    let cs = acmp.cs.read();
+    if (cs & reg::ACMP_CS_ACF) == 0 {
+        return;
+    }
+    acmp.cs.write(cs & !reg::ACMP_CS_ACF);
+    if let Some(callback) = CALLBACKS[id as usize] {
+        callback(id, (cs & reg::ACMP_CS_ACO) != 0);
+    }
+}
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
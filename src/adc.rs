@@ -0,0 +1,588 @@
+//! # ADC for the KE06Z
+//!
+//! The KE06Z has one 12-bit successive approximation ADC, with sixteen
+//! external channels and four internal ones.
+//!
+//! The ADC has an eight entry FIFO, so up to eight channels can be
+//! converted in one scan, with a single interrupt at the end.
+//!
+//! Scans can be started by software, or by a hardware trigger (selected
+//! in SIM_SOPT0) for jitter-free sampling at a fixed rate.
+//!
+//! The compare function lets the ADC convert continuously, and only
+//! interrupt when a reading crosses a threshold.
+//!
+//! The internal bandgap and temperature sensor channels give the supply
+//! voltage and die temperature, using the formulas from the reference
+//! manual.
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use cortex_m::asm::nop;
+
+use super::power;
+use super::registers as reg;
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+/// An ADC input
+#[derive(PartialEq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum Channel {
+    Ad0,
+    Ad1,
+    Ad2,
+    Ad3,
+    Ad4,
+    Ad5,
+    Ad6,
+    Ad7,
+    Ad8,
+    Ad9,
+    Ad10,
+    Ad11,
+    Ad12,
+    Ad13,
+    Ad14,
+    Ad15,
+    /// The on-die temperature sensor
+    Temperature,
+    /// The internal bandgap reference (about 1.16 V). Reading it locks
+    /// the low-voltage detect settings - see
+    /// `power::enable_bandgap_buffer`.
+    Bandgap,
+    /// The high reference voltage
+    VrefH,
+    /// The low reference voltage
+    VrefL,
+}
+
+/// How many bits each result has
+#[derive(PartialEq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum Resolution {
+    Bits8,
+    Bits10,
+    Bits12,
+}
+
+/// Where the ADC gets its clock from
+#[derive(PartialEq, Clone, Copy)]
+pub enum ClockSource {
+    /// The bus clock
+    Bus,
+    /// The bus clock divided by two
+    BusDiv2,
+    /// The alternate clock (the oscillator output)
+    Alternate,
+    /// The ADC's own asynchronous clock, which keeps running in Stop mode
+    Async,
+}
+
+/// Divides the selected clock down to give the ADC clock (which should
+/// be 8 MHz or less)
+#[derive(PartialEq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum Divider {
+    Div1,
+    Div2,
+    Div4,
+    Div8,
+}
+
+/// How long the input is sampled for
+#[derive(PartialEq, Clone, Copy)]
+pub enum SampleTime {
+    /// 3.5 ADC clocks
+    Short,
+    /// 23.5 ADC clocks, for high impedance sources
+    Long,
+}
+
+/// What starts a conversion (or a scan)
+#[derive(PartialEq, Clone, Copy)]
+pub enum Trigger {
+    /// Writing the channel to SC1 (the default)
+    Software,
+    /// RTC overflow
+    RtcOverflow,
+    /// FTM0 initialization trigger
+    Ftm0Init,
+    /// FTM2 initialization trigger, delayed by the given number of bus
+    /// clocks
+    Ftm2Init(u8),
+    /// FTM2 match trigger, delayed by the given number of bus clocks
+    Ftm2Match(u8),
+    /// PIT channel 0 overflow
+    Pit0,
+    /// PIT channel 1 overflow
+    Pit1,
+    /// ACMP0 output
+    Acmp0,
+    /// ACMP1 output
+    Acmp1,
+}
+
+/// The condition which makes a monitored channel raise an alarm
+#[derive(PartialEq, Clone, Copy)]
+pub enum Compare {
+    /// The result is less than the given value
+    Below(u16),
+    /// The result is greater than or equal to the given value
+    AtOrAbove(u16),
+}
+
+/// The ways a request can be wrong
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AdcError {
+    /// A scan must have between one and eight channels
+    BadChannelCount,
+}
+
+/// Called from interrupt context when a scan completes, with one result
+/// per channel, in the order the channels were given.
+pub type ScanCallback = fn(results: &[u16]);
+
+/// Called from interrupt context when a monitored channel meets its
+/// compare condition, with the result which did so.
+pub type CompareCallback = fn(result: u16);
+
+/// Controls the ADC
+pub struct Adc {
+    resolution: Resolution,
+    trigger: Trigger,
+    reg: &'static mut reg::AdcRegisters,
+}
+
+// ****************************************************************************
+//
+// Private Types
+//
+// ****************************************************************************
+
+/// The scan in progress, shared with the interrupt handler
+struct ScanState {
+    count: usize,
+    callback: Option<ScanCallback>,
+    results: [u16; FIFO_LEN],
+}
+
+// ****************************************************************************
+//
+// Public Data
+//
+// ****************************************************************************
+
+/// The most channels a scan can have
+pub const FIFO_LEN: usize = 8;
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+/// Writing this to ADCH disables the ADC
+const CHANNEL_DISABLED: u32 = 0x1F;
+
+/// The bandgap reference voltage, in mV (from the data sheet)
+const BANDGAP_MV: u32 = 1160;
+
+/// The temperature sensor voltage at 25 degrees C, in uV
+const VTEMP25_UV: i32 = 1_396_000;
+
+/// The temperature sensor slope below 25 degrees C, in uV per degree
+const TEMP_SLOPE_COLD_UV: i32 = 3266;
+
+/// The temperature sensor slope above 25 degrees C, in uV per degree
+const TEMP_SLOPE_HOT_UV: i32 = 3638;
+
+/// Only touched by the interrupt handler while the ADC interrupt is
+/// enabled.
+static mut SCAN_STATE: ScanState = ScanState {
+    count: 0,
+    callback: None,
+    results: [0; FIFO_LEN],
+};
+
+/// The callback for a monitored channel, if it has one. Only touched by
+/// the interrupt handler while the ADC interrupt is enabled.
+static mut COMPARE_CALLBACK: Option<CompareCallback> = None;
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+/// Represents the ADC on the KE06Z
+impl Adc {
+    /// Create a new Adc object. The caller is responsible for ensuring
+    /// that only one object exists. Conversions are software triggered.
+    pub fn new(resolution: Resolution,
+               clock: ClockSource,
+               divider: Divider,
+               sample_time: SampleTime)
+               -> Adc {
+        let mut adc = Adc {
+            resolution: resolution,
+            trigger: Trigger::Software,
+            reg: reg::get_adc(),
+        };
+
+        // Enable the ADC peripheral
+        reg::get_sim().scgc.modify(|x| x | reg::SIM_SCGC_ADC);
+
+        // Stop any conversion in progress
+        adc.reg.sc1.write(reg::adc_sc1_adch(CHANNEL_DISABLED));
+        adc.reg.sc2.write(0);
+
+        let clock = match clock {
+            ClockSource::Bus => 0,
+            ClockSource::BusDiv2 => 1,
+            ClockSource::Alternate => 2,
+            ClockSource::Async => 3,
+        };
+        let divider = match divider {
+            Divider::Div1 => 0,
+            Divider::Div2 => 1,
+            Divider::Div4 => 2,
+            Divider::Div8 => 3,
+        };
+        let sample_time = match sample_time {
+            SampleTime::Short => 0,
+            SampleTime::Long => reg::ADC_SC3_ADLSMP,
+        };
+        adc.reg.sc3.write(reg::adc_sc3_adiclk(clock) | reg::adc_sc3_adiv(divider) | sample_time);
+        adc.set_resolution(resolution);
+
+        adc
+    }
+
+    /// Select what starts a scan. With a hardware trigger, `start_scan`
+    /// just arms the ADC, and every trigger converts the whole list of
+    /// channels. Results are delivered to the scan callback, or can be
+    /// polled from the FIFO as usual.
+    pub fn set_trigger(&mut self, trigger: Trigger) {
+        // Abort anything in progress
+        self.reg.sc1.write(reg::adc_sc1_adch(CHANNEL_DISABLED));
+        self.trigger = trigger;
+        let (adhwt, delay) = match trigger {
+            Trigger::Software => {
+                self.reg.sc2.modify(|x| x & !reg::ADC_SC2_ADTRG);
+                return;
+            }
+            Trigger::RtcOverflow => (0, None),
+            Trigger::Ftm0Init => (1, None),
+            Trigger::Ftm2Init(delay) => (2, Some(delay)),
+            Trigger::Ftm2Match(delay) => (3, Some(delay)),
+            Trigger::Pit0 => (4, None),
+            Trigger::Pit1 => (5, None),
+            Trigger::Acmp0 => (6, None),
+            Trigger::Acmp1 => (7, None),
+        };
+        reg::get_sim().sopt0.modify(|x| {
+            let x = x & !(reg::SIM_SOPT0_ADHWT | reg::SIM_SOPT0_DELAY);
+            let x = x | reg::sim_sopt0_adhwt(adhwt);
+            match delay {
+                Some(delay) => x | reg::sim_sopt0_delay(delay as u32),
+                None => x,
+            }
+        });
+        self.reg.sc2.modify(|x| x | reg::ADC_SC2_ADTRG);
+    }
+
+    /// Change the resolution.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = resolution;
+        let mode = match resolution {
+            Resolution::Bits8 => 0,
+            Resolution::Bits10 => 1,
+            Resolution::Bits12 => 2,
+        };
+        self.reg.sc3.modify(|x| (x & !reg::ADC_SC3_MODE) | reg::adc_sc3_mode(mode));
+    }
+
+    /// Get the resolution.
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    /// The largest result the current resolution can give.
+    pub fn max_value(&self) -> u16 {
+        match self.resolution {
+            Resolution::Bits8 => 0xFF,
+            Resolution::Bits10 => 0x3FF,
+            Resolution::Bits12 => 0xFFF,
+        }
+    }
+
+    /// Perform a single conversion, busy-waiting for the result. The
+    /// trigger must be `Trigger::Software`.
+    pub fn read(&mut self, channel: Channel) -> u16 {
+        enable_channel(channel);
+        // No FIFO and no compare function
+        self.reg.sc4.modify(|x| x & !reg::ADC_SC4_AFDEP);
+        self.reg.sc2.modify(|x| x & !(reg::ADC_SC2_ACFE | reg::ADC_SC2_ACFGT));
+        // Writing SC1 starts a conversion
+        self.reg.sc1.write(reg::adc_sc1_adch(channel_number(channel)));
+        while (self.reg.sc1.read() & reg::ADC_SC1_COCO) == 0 {
+            nop();
+        }
+        (self.reg.r.read() & reg::ADC_R_ADR) as u16
+    }
+
+    /// Measure the supply voltage, in mV, by converting the bandgap
+    /// against VREFH. Only valid when VREFH is tied to VDD (as it is on
+    /// the Freedom board). Use a long sample time for best accuracy.
+    pub fn read_vdd_millivolts(&mut self) -> u32 {
+        let bandgap = self.read(Channel::Bandgap) as u32;
+        if bandgap == 0 {
+            return 0;
+        }
+        let max = self.max_value() as u32;
+        // VDD = Vbg * max / reading, rounded
+        (BANDGAP_MV * max + (bandgap / 2)) / bandgap
+    }
+
+    /// Measure the die temperature, in whole degrees C. VDD is measured
+    /// first, so the result doesn't depend on the supply. As with
+    /// `read_vdd_millivolts`, VREFH must be tied to VDD.
+    pub fn read_temperature_celsius(&mut self) -> i32 {
+        let vdd = self.read_vdd_millivolts();
+        let reading = self.read(Channel::Temperature) as u32;
+        let max = self.max_value() as u32;
+        // Fits in a u32 for any 12-bit reading and VDD under 10 V
+        let vtemp_uv = ((reading * vdd * 100) / max) as i32 * 10;
+        // Temp = 25 - ((Vtemp - Vtemp25) / m). The sensor voltage falls
+        // as the die warms up, with a different slope either side of 25.
+        let diff = vtemp_uv - VTEMP25_UV;
+        let slope = if diff < 0 {
+            TEMP_SLOPE_HOT_UV
+        } else {
+            TEMP_SLOPE_COLD_UV
+        };
+        let rounding = if diff < 0 { -slope / 2 } else { slope / 2 };
+        25 - ((diff + rounding) / slope)
+    }
+
+    /// Convert up to eight channels in one go, busy-waiting for the
+    /// results. `results` gets one result per channel. With a hardware
+    /// trigger, this waits for the next trigger.
+    pub fn scan(&mut self, channels: &[Channel], results: &mut [u16]) -> Result<(), AdcError> {
+        self.start_scan(channels, None)?;
+        while !self.scan_complete() {
+            nop();
+        }
+        self.read_scan(results);
+        Ok(())
+    }
+
+    /// Start converting up to eight channels. If `callback` is given, the
+    /// ADC interrupt is enabled and `adc_isr` (which must be in the vector
+    /// table and enabled) collects the results and passes them to the
+    /// callback. Otherwise, poll `scan_complete` and then call
+    /// `read_scan`.
+    pub fn start_scan(&mut self,
+                      channels: &[Channel],
+                      callback: Option<ScanCallback>)
+                      -> Result<(), AdcError> {
+        let count = channels.len();
+        if count == 0 || count > FIFO_LEN {
+            return Err(AdcError::BadChannelCount);
+        }
+        for &channel in channels {
+            enable_channel(channel);
+        }
+        // Abort anything in progress
+        self.reg.sc1.write(reg::adc_sc1_adch(CHANNEL_DISABLED));
+        // No compare function
+        self.reg.sc2.modify(|x| x & !(reg::ADC_SC2_ACFE | reg::ADC_SC2_ACFGT));
+        unsafe {
+            COMPARE_CALLBACK = None;
+            SCAN_STATE.count = count;
+            SCAN_STATE.callback = callback;
+        }
+        // A FIFO depth of N + 1 - or no FIFO for a single channel
+        let afdep = reg::adc_sc4_afdep((count - 1) as u32);
+        self.reg.sc4.modify(|x| (x & !reg::ADC_SC4_AFDEP) | afdep);
+        let aien = if callback.is_some() {
+            reg::ADC_SC1_AIEN
+        } else {
+            0
+        };
+        // With a hardware trigger, convert every channel on each trigger.
+        // Continuous scanning (ASCANE) stays off - each trigger gets one
+        // pass over the channel list.
+        self.reg.sc4.modify(|x| x & !(reg::ADC_SC4_HTRGME | reg::ADC_SC4_ASCANE));
+        if self.trigger != Trigger::Software && count > 1 {
+            self.reg.sc4.modify(|x| x | reg::ADC_SC4_HTRGME);
+        }
+        // With a software trigger, the conversions start once the FIFO is
+        // full of channels
+        for &channel in channels {
+            self.reg.sc1.write(reg::adc_sc1_adch(channel_number(channel)) | aien);
+        }
+        Ok(())
+    }
+
+    /// Convert `channel` continuously, but only flag a result when it
+    /// meets the `compare` condition. If `callback` is given, the ADC
+    /// interrupt is enabled and `adc_isr` (which must be in the vector
+    /// table and enabled) passes the result to it. The interrupt is then
+    /// disabled (conversions continue) until this is called again, so the
+    /// callback fires once per alarm. Otherwise, poll `monitor_result`.
+    ///
+    /// This replaces any scan in progress. The trigger must be
+    /// `Trigger::Software`.
+    pub fn start_monitor(&mut self,
+                         channel: Channel,
+                         compare: Compare,
+                         callback: Option<CompareCallback>) {
+        enable_channel(channel);
+        // Abort anything in progress
+        self.reg.sc1.write(reg::adc_sc1_adch(CHANNEL_DISABLED));
+        let fifo = reg::ADC_SC4_AFDEP | reg::ADC_SC4_ASCANE | reg::ADC_SC4_HTRGME;
+        self.reg.sc4.modify(|x| x & !fifo);
+        unsafe {
+            SCAN_STATE.callback = None;
+            COMPARE_CALLBACK = callback;
+        }
+        let (value, greater) = match compare {
+            Compare::Below(value) => (value, 0),
+            Compare::AtOrAbove(value) => (value, reg::ADC_SC2_ACFGT),
+        };
+        self.reg.cv.write(value as u32);
+        self.reg.sc2.modify(|x| (x & !reg::ADC_SC2_ACFGT) | reg::ADC_SC2_ACFE | greater);
+        let aien = if callback.is_some() {
+            reg::ADC_SC1_AIEN
+        } else {
+            0
+        };
+        self.reg.sc1.write(reg::adc_sc1_adch(channel_number(channel)) | reg::ADC_SC1_ADCO | aien);
+    }
+
+    /// Returns the result which met the compare condition, if one has
+    /// since the last call.
+    pub fn monitor_result(&mut self) -> Option<u16> {
+        if (self.reg.sc1.read() & reg::ADC_SC1_COCO) != 0 {
+            Some((self.reg.r.read() & reg::ADC_R_ADR) as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Stop monitoring and turn the compare function off.
+    pub fn stop_monitor(&mut self) {
+        self.reg.sc1.write(reg::adc_sc1_adch(CHANNEL_DISABLED));
+        self.reg.sc2.modify(|x| x & !(reg::ADC_SC2_ACFE | reg::ADC_SC2_ACFGT));
+        unsafe {
+            COMPARE_CALLBACK = None;
+        }
+    }
+
+    /// Returns true when every channel in the scan has been converted.
+    pub fn scan_complete(&self) -> bool {
+        (self.reg.sc1.read() & reg::ADC_SC1_COCO) != 0
+    }
+
+    /// Empty the result FIFO into `results`, returning how many results
+    /// there were.
+    pub fn read_scan(&mut self, results: &mut [u16]) -> usize {
+        let count = unsafe { SCAN_STATE.count };
+        read_fifo(self.reg, count, results)
+    }
+}
+
+/// Called when the ADC interrupt fires
+pub unsafe extern "C" fn adc_isr() {
+    let adc = reg::get_adc();
+    if (adc.sc1.read() & reg::ADC_SC1_COCO) == 0 {
+        return;
+    }
+    if let Some(callback) = COMPARE_CALLBACK {
+        let value = (adc.r.read() & reg::ADC_R_ADR) as u16;
+        // One alarm at a time. Rewriting SC1 restarts the conversions.
+        adc.sc1.modify(|x| x & !reg::ADC_SC1_AIEN);
+        callback(value);
+        return;
+    }
+    let count = read_fifo(adc, SCAN_STATE.count, &mut SCAN_STATE.results);
+    if let Some(callback) = SCAN_STATE.callback {
+        callback(&SCAN_STATE.results[..count]);
+    }
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+/// Convert a channel to its ADCH value.
+fn channel_number(channel: Channel) -> u32 {
+    match channel {
+        Channel::Ad0 => 0,
+        Channel::Ad1 => 1,
+        Channel::Ad2 => 2,
+        Channel::Ad3 => 3,
+        Channel::Ad4 => 4,
+        Channel::Ad5 => 5,
+        Channel::Ad6 => 6,
+        Channel::Ad7 => 7,
+        Channel::Ad8 => 8,
+        Channel::Ad9 => 9,
+        Channel::Ad10 => 10,
+        Channel::Ad11 => 11,
+        Channel::Ad12 => 12,
+        Channel::Ad13 => 13,
+        Channel::Ad14 => 14,
+        Channel::Ad15 => 15,
+        Channel::Temperature => 0x16,
+        Channel::Bandgap => 0x17,
+        Channel::VrefH => 0x1D,
+        Channel::VrefL => 0x1E,
+    }
+}
+
+/// Read `count` results from the FIFO into `results` (dropping any that
+/// don't fit). Reading the last one clears COCO. Returns how many were
+/// stored.
+fn read_fifo(adc: &mut reg::AdcRegisters, count: usize, results: &mut [u16]) -> usize {
+    for n in 0..count {
+        let value = (adc.r.read() & reg::ADC_R_ADR) as u16;
+        if let Some(slot) = results.get_mut(n) {
+            *slot = value;
+        }
+    }
+    if count < results.len() {
+        count
+    } else {
+        results.len()
+    }
+}
+
+/// Get a channel ready for use. External channels have their pin's
+/// digital input disabled, and the bandgap needs its buffer turning on.
+fn enable_channel(channel: Channel) {
+    let number = channel_number(channel);
+    if number < 16 {
+        reg::get_adc().apctl1.modify(|x| x | (1 << number));
+    } else if channel == Channel::Bandgap {
+        power::enable_bandgap_buffer();
+    }
+}
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
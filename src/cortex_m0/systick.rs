@@ -1,9 +1,14 @@
-//! # SysTick for the Cortex-M4F
+//! # SysTick for the Cortex-M0+
 //!
-//! Each Cortex-M4 has a timer peripheral typically used for OS scheduling tick.
-//! Here we configure it as a countdown timer that overflows every 2**24 ticks
-//! (so about once a second at 16MHz), and maintain a separate atomic overflow
-//! count to accurately track time since power-up.
+//! Each Cortex-M0+ has a timer peripheral typically used for OS scheduling
+//! tick. Here we configure it as a countdown timer that overflows every 2**24
+//! ticks (so about every 420ms at 40MHz), and maintain a separate atomic
+//! overflow count to accurately track time since power-up.
+//!
+//! SysTick is clocked from the core clock (`CLOCK_HZ`), rather than the
+//! external reference, so that it has enough resolution to time individual
+//! UART bits (see `Uart::autobaud`). All timeouts in this crate are given
+//! in these ticks - use `TICKS_PER_MS` to convert.
 
 // ****************************************************************************
 //
@@ -30,6 +35,12 @@ use cortex_m::peripheral as cm_periph;
 /// SysTick is a 24-bit timer
 pub const SYSTICK_MAX: usize = (1 << 24) - 1;
 
+/// SysTick runs from the core clock, which `ics::init` sets to 40 MHz
+pub const CLOCK_HZ: u32 = 40_000_000;
+
+/// How many SysTick ticks there are in a millisecond
+pub const TICKS_PER_MS: usize = (CLOCK_HZ / 1000) as usize;
+
 /// total number of times SysTick has wrapped
 pub static mut SYSTICK_WRAP_COUNT: usize = 0;
 
@@ -52,6 +63,7 @@ pub static mut SYSTICK_WRAP_COUNT: usize = 0;
 // The following are defines for the bit fields in the NVIC_ST_CTRL register.
 //
 // *****************************************************************************
+const NVIC_ST_CTRL_CLK_SRC: usize = 0x00000004; // Clock Source
 const NVIC_ST_CTRL_INTEN: usize = 0x00000002; // Interrupt Enable
 const NVIC_ST_CTRL_ENABLE: usize = 0x00000001; // Enable
 
@@ -63,15 +75,16 @@ const NVIC_ST_CTRL_ENABLE: usize = 0x00000001; // Enable
 
 /// Initialises the SysTick system.
 ///
-/// We configure SysTick to run at PIOSC / 4, with the full 24 bit range.
+/// We configure SysTick to run at the core clock (`CLOCK_HZ`), with the full
+/// 24 bit range.
 pub fn init() {
     unsafe {
         let syst = cm_periph::syst_mut();
         syst.rvr.write(SYSTICK_MAX as u32);
         // A write to current resets the timer
         syst.cvr.write(0);
-        // Set to multi-shot mode, with interrupts on and on the PIOSC / 4
-        syst.csr.write((NVIC_ST_CTRL_ENABLE | NVIC_ST_CTRL_INTEN) as u32);
+        // Set to multi-shot mode, with interrupts on and on the core clock
+        syst.csr.write((NVIC_ST_CTRL_ENABLE | NVIC_ST_CTRL_INTEN | NVIC_ST_CTRL_CLK_SRC) as u32);
     }
 }
 
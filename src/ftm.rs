@@ -0,0 +1,526 @@
+//! # FlexTimer PWM for the KE06Z
+//!
+//! The KE06Z has three FlexTimers. FTM0 and FTM1 have two channels each,
+//! and FTM2 has six. Every channel on a timer shares the timer's period,
+//! so the frequency is set per timer and the duty cycle per channel.
+//!
+//! The frequency is given in Hz, and the prescaler and modulo are worked
+//! out from the timer clock. Duty cycles are given in per-mille (0 to
+//! 1000), so they survive a change of frequency.
+//!
+//! Duty cycle changes never produce a glitch. FTM0 and FTM1 buffer new
+//! channel values in hardware until the end of the current period. FTM2
+//! is put in enhanced synchronization mode, and each update is loaded at
+//! the next counter maximum by a software trigger.
+//!
+//! Each channel drives its default pin (see SIM_PINSEL) once enabled.
+//!
+//! FTM2 can also react to the outside world. A fault input drives every
+//! channel to its inactive level until the fault goes away (e.g. an
+//! over-current trip from a comparator), and a hardware trigger (e.g. an
+//! ACMP output, see `acmp::Acmp::route_to_ftm2_trigger`) can restart the
+//! period. FTM2 can in turn raise the external trigger used by the ADC at
+//! the start of each period or on a channel match.
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use super::registers as reg;
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+/// Selects which FlexTimer we're talking about
+#[derive(PartialEq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum FtmId {
+    Ftm0,
+    Ftm1,
+    Ftm2,
+}
+
+/// A timer channel. FTM0 and FTM1 only have `Ch0` and `Ch1`.
+#[derive(PartialEq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum Channel {
+    Ch0,
+    Ch1,
+    Ch2,
+    Ch3,
+    Ch4,
+    Ch5,
+}
+
+/// How the PWM pulses sit within each period
+#[derive(PartialEq, Clone, Copy)]
+pub enum Alignment {
+    /// Pulses start at the beginning of each period. The counter counts
+    /// up from zero to the modulo.
+    Edge,
+    /// Pulses are centred in each period. The counter counts up to the
+    /// modulo and back down, so the modulo is half as big.
+    Center,
+}
+
+/// Which level is the active part of the duty cycle
+#[derive(PartialEq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// One of FTM2's fault inputs. Two are pins, and two are wired to the
+/// analog comparators (see `acmp::Acmp::ftm2_fault_input`).
+#[derive(PartialEq, Clone, Copy)]
+pub enum FaultInput {
+    /// The FTM2_FLT1 pin
+    Fault0,
+    /// The ACMP0 output
+    Fault1,
+    /// The ACMP1 output
+    Fault2,
+    /// The FTM2_FLT2 pin
+    Fault3,
+}
+
+/// What happens once a fault goes away
+#[derive(PartialEq, Clone, Copy)]
+pub enum FaultClearing {
+    /// The channels stay inactive until `clear_fault` is called
+    Manual,
+    /// The channels start again at the next period. With a comparator
+    /// as the fault input, this gives cycle-by-cycle current limiting.
+    Automatic,
+}
+
+/// One of FTM2's hardware trigger inputs
+#[derive(PartialEq, Clone, Copy)]
+pub enum HardwareTrigger {
+    /// The ACMP output selected with `acmp::Acmp::route_to_ftm2_trigger`
+    Trig0,
+    #[allow(missing_docs)]
+    Trig1,
+    #[allow(missing_docs)]
+    Trig2,
+}
+
+/// What makes FTM2 raise its external trigger
+#[derive(PartialEq, Clone, Copy)]
+pub enum TriggerSource {
+    /// The counter starting a new period. See `adc::Trigger::Ftm2Init`.
+    Init,
+    /// The counter matching a channel's value. See
+    /// `adc::Trigger::Ftm2Match`.
+    Match(Channel),
+}
+
+/// The ways a request can be wrong
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FtmError {
+    /// This timer doesn't have that channel
+    BadChannel,
+    /// Only FTM2 can do that
+    NotSupported,
+}
+
+/// Controls a FlexTimer
+pub struct Ftm {
+    id: FtmId,
+    alignment: Alignment,
+    /// The prescaler, as a power of two
+    prescale: u32,
+    modulo: u32,
+    /// Each channel's duty cycle, in per-mille
+    duty: [u16; MAX_CHANNELS],
+    reg: &'static mut reg::FtmRegisters,
+}
+
+// ****************************************************************************
+//
+// Private Types
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// Public Data
+//
+// ****************************************************************************
+
+/// A duty cycle of 100%
+pub const DUTY_MAX: u16 = 1000;
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+/// The Freedom board has an 8 MHz external oscillator
+/// We set the timer clock to 20 MHz
+const CLOCK_SPEED: u32 = 20_000_000;
+
+/// FTM2 has the most channels
+const MAX_CHANNELS: usize = 6;
+
+/// The largest prescaler, as a power of two
+const MAX_PRESCALE: u32 = 7;
+
+/// Selects the timer clock in SC_CLKS
+const CLKS_SYSTEM: u32 = 1;
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+/// Represents a FlexTimer on the KE06Z
+impl Ftm {
+    /// Create a new Ftm object, running at the nearest frequency to
+    /// `frequency` (in Hz) we can manage. The caller is responsible for
+    /// ensuring that only one object exists per timer. All channels start
+    /// disabled.
+    pub fn new(id: FtmId, alignment: Alignment, frequency: u32) -> Ftm {
+        let mut ftm = Ftm {
+            id: id,
+            alignment: alignment,
+            prescale: 0,
+            modulo: 0,
+            duty: [0; MAX_CHANNELS],
+            reg: get_ftm_registers(id),
+        };
+
+        // Enable the FTM peripheral
+        reg::get_sim().scgc.modify(|x| {
+            x |
+            match id {
+                FtmId::Ftm0 => reg::SIM_SCGC_FTM0,
+                FtmId::Ftm1 => reg::SIM_SCGC_FTM1,
+                FtmId::Ftm2 => reg::SIM_SCGC_FTM2,
+            }
+        });
+
+        // Stop the counter while we set it up
+        ftm.reg.sc.write(0);
+        for n in 0..ftm.channel_count() {
+            ftm.reg.channels[n].sc.write(0);
+        }
+        ftm.reg.cntin.write(0);
+
+        if id == FtmId::Ftm2 {
+            // Enhanced synchronization, with MOD and CnV loaded from
+            // their buffers by a software trigger at the counter maximum.
+            ftm.reg.mode.write(reg::FTM_MODE_WPDIS | reg::FTM_MODE_FTMEN);
+            ftm.reg.synconf.write(reg::FTM_SYNCONF_SYNCMODE | reg::FTM_SYNCONF_SWWRBUF);
+            ftm.reg.combine.write(reg::FTM_COMBINE_SYNCEN0 | reg::FTM_COMBINE_SYNCEN1 |
+                                  reg::FTM_COMBINE_SYNCEN2);
+            ftm.reg.sync.write(reg::FTM_SYNC_CNTMAX);
+        }
+
+        ftm.set_frequency(frequency);
+        ftm
+    }
+
+    /// Change the PWM frequency (in Hz). Returns the frequency we
+    /// actually managed. Every enabled channel keeps its duty cycle. The
+    /// counter restarts, so the current period is cut short.
+    pub fn set_frequency(&mut self, frequency: u32) -> u32 {
+        let (prescale, modulo) = calculate_timing(frequency, self.alignment);
+        self.prescale = prescale;
+        self.modulo = modulo;
+
+        // With the clock stopped, MOD and CnV update as soon as they're
+        // written.
+        self.reg.sc.write(0);
+        self.reg.modulo.write(modulo);
+        for n in 0..self.channel_count() {
+            let value = self.channel_value(self.duty[n]);
+            self.reg.channels[n].v.write(value);
+        }
+        self.reg.cnt.write(0);
+        let cpwms = match self.alignment {
+            Alignment::Edge => 0,
+            Alignment::Center => reg::FTM_SC_CPWMS,
+        };
+        self.reg.sc.write(reg::ftm_sc_clks(CLKS_SYSTEM) | reg::ftm_sc_ps(prescale) | cpwms);
+        self.frequency()
+    }
+
+    /// The PWM frequency, in Hz.
+    pub fn frequency(&self) -> u32 {
+        let clock = CLOCK_SPEED >> self.prescale;
+        match self.alignment {
+            Alignment::Edge => clock / (self.modulo + 1),
+            Alignment::Center => clock / (2 * self.modulo),
+        }
+    }
+
+    /// Start PWM on `channel`, with `duty` in per-mille (anything over
+    /// `DUTY_MAX` counts as 100%).
+    pub fn enable_channel(&mut self,
+                          channel: Channel,
+                          polarity: Polarity,
+                          duty: u16)
+                          -> Result<(), FtmError> {
+        let n = self.channel_number(channel)?;
+        let els = match polarity {
+            Polarity::ActiveHigh => reg::FTM_CNSC_ELSB,
+            Polarity::ActiveLow => reg::FTM_CNSC_ELSA,
+        };
+        self.reg.channels[n].sc.write(reg::FTM_CNSC_MSB | els);
+        self.set_duty(channel, duty)
+    }
+
+    /// Stop PWM on `channel`, giving its pin back to the port.
+    pub fn disable_channel(&mut self, channel: Channel) -> Result<(), FtmError> {
+        let n = self.channel_number(channel)?;
+        self.reg.channels[n].sc.write(0);
+        Ok(())
+    }
+
+    /// Change the duty cycle on `channel`, in per-mille (anything over
+    /// `DUTY_MAX` counts as 100%). The new duty cycle starts with the
+    /// next period.
+    pub fn set_duty(&mut self, channel: Channel, duty: u16) -> Result<(), FtmError> {
+        let n = self.channel_number(channel)?;
+        let duty = if duty > DUTY_MAX { DUTY_MAX } else { duty };
+        self.duty[n] = duty;
+        let value = self.channel_value(duty);
+        self.reg.channels[n].v.write(value);
+        if self.id == FtmId::Ftm2 {
+            self.reg.sync.modify(|x| x | reg::FTM_SYNC_SWSYNC);
+        }
+        Ok(())
+    }
+
+    /// Get the duty cycle on `channel`, in per-mille.
+    pub fn duty(&self, channel: Channel) -> Result<u16, FtmError> {
+        let n = self.channel_number(channel)?;
+        Ok(self.duty[n])
+    }
+
+    /// Drive every channel to its inactive level while `input` is active.
+    /// `polarity` is the level of the input which signals a fault.
+    pub fn enable_fault(&mut self,
+                        input: FaultInput,
+                        polarity: Polarity,
+                        clearing: FaultClearing)
+                        -> Result<(), FtmError> {
+        self.check_ftm2()?;
+        let mask = reg::FTM_FLTCTRL_FAULT0EN << input as u32;
+        match polarity {
+            Polarity::ActiveHigh => self.reg.fltpol.modify(|x| x & !mask),
+            Polarity::ActiveLow => self.reg.fltpol.modify(|x| x | mask),
+        }
+        self.reg.fltctrl.modify(|x| x | mask);
+        self.reg.combine.modify(|x| {
+            x | reg::FTM_COMBINE_FAULTEN0 | reg::FTM_COMBINE_FAULTEN1 | reg::FTM_COMBINE_FAULTEN2
+        });
+        // Fault control on all channels, cleared by hand or at the next
+        // period.
+        let faultm = match clearing {
+            FaultClearing::Manual => 2,
+            FaultClearing::Automatic => 3,
+        };
+        self.reg.mode.modify(|x| (x & !reg::FTM_MODE_FAULTM) | reg::ftm_mode_faultm(faultm));
+        Ok(())
+    }
+
+    /// Stop watching `input`. Fault control is turned off once no inputs
+    /// are left.
+    pub fn disable_fault(&mut self, input: FaultInput) -> Result<(), FtmError> {
+        self.check_ftm2()?;
+        let mask = reg::FTM_FLTCTRL_FAULT0EN << input as u32;
+        self.reg.fltctrl.modify(|x| x & !mask);
+        let inputs = reg::FTM_FLTCTRL_FAULT0EN | reg::FTM_FLTCTRL_FAULT1EN |
+                     reg::FTM_FLTCTRL_FAULT2EN | reg::FTM_FLTCTRL_FAULT3EN;
+        if (self.reg.fltctrl.read() & inputs) == 0 {
+            self.reg.mode.modify(|x| x & !reg::FTM_MODE_FAULTM);
+            self.reg.combine.modify(|x| {
+                x & !(reg::FTM_COMBINE_FAULTEN0 | reg::FTM_COMBINE_FAULTEN1 |
+                      reg::FTM_COMBINE_FAULTEN2)
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns true (and clears the flag) if a fault has occurred since
+    /// the last call. With `FaultClearing::Manual`, the channels start
+    /// again at the next period, as long as the fault has gone away.
+    pub fn clear_fault(&mut self) -> Result<bool, FtmError> {
+        self.check_ftm2()?;
+        let fms = self.reg.fms.read();
+        if (fms & reg::FTM_FMS_FAULTF) != 0 {
+            // FAULTF is cleared by reading it as one and writing a zero.
+            // Writing a one to WPEN would write-protect the timer.
+            self.reg.fms.write(fms & !(reg::FTM_FMS_FAULTF | reg::FTM_FMS_WPEN));
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Restart the period whenever `trigger` fires, loading any new duty
+    /// cycles at the same time.
+    pub fn enable_hardware_trigger(&mut self, trigger: HardwareTrigger) -> Result<(), FtmError> {
+        self.check_ftm2()?;
+        // Keep the trigger armed after each one
+        self.reg.synconf.modify(|x| {
+            x | reg::FTM_SYNCONF_HWTRIGMODE | reg::FTM_SYNCONF_HWRSTCNT |
+            reg::FTM_SYNCONF_HWWRBUF
+        });
+        self.reg.sync.modify(|x| x | hardware_trigger_mask(trigger));
+        Ok(())
+    }
+
+    /// Stop acting on `trigger`.
+    pub fn disable_hardware_trigger(&mut self, trigger: HardwareTrigger) -> Result<(), FtmError> {
+        self.check_ftm2()?;
+        self.reg.sync.modify(|x| x & !hardware_trigger_mask(trigger));
+        Ok(())
+    }
+
+    /// Raise the external trigger on `source`. Several sources can be
+    /// enabled at once.
+    pub fn enable_trigger_output(&mut self, source: TriggerSource) -> Result<(), FtmError> {
+        self.check_ftm2()?;
+        let mask = self.trigger_output_mask(source)?;
+        self.reg.exttrig.modify(|x| x | mask);
+        Ok(())
+    }
+
+    /// Stop raising the external trigger on `source`.
+    pub fn disable_trigger_output(&mut self, source: TriggerSource) -> Result<(), FtmError> {
+        self.check_ftm2()?;
+        let mask = self.trigger_output_mask(source)?;
+        self.reg.exttrig.modify(|x| x & !mask);
+        Ok(())
+    }
+
+    /// Fault control and triggers are only on FTM2.
+    fn check_ftm2(&self) -> Result<(), FtmError> {
+        if self.id == FtmId::Ftm2 {
+            Ok(())
+        } else {
+            Err(FtmError::NotSupported)
+        }
+    }
+
+    /// The EXTTRIG bit for a trigger source.
+    fn trigger_output_mask(&self, source: TriggerSource) -> Result<u32, FtmError> {
+        match source {
+            TriggerSource::Init => Ok(reg::FTM_EXTTRIG_INITTRIGEN),
+            TriggerSource::Match(channel) => {
+                let mask = match self.channel_number(channel)? {
+                    0 => reg::FTM_EXTTRIG_CH0TRIG,
+                    1 => reg::FTM_EXTTRIG_CH1TRIG,
+                    2 => reg::FTM_EXTTRIG_CH2TRIG,
+                    3 => reg::FTM_EXTTRIG_CH3TRIG,
+                    4 => reg::FTM_EXTTRIG_CH4TRIG,
+                    _ => reg::FTM_EXTTRIG_CH5TRIG,
+                };
+                Ok(mask)
+            }
+        }
+    }
+
+    /// How many channels this timer has.
+    fn channel_count(&self) -> usize {
+        match self.id {
+            FtmId::Ftm0 | FtmId::Ftm1 => 2,
+            FtmId::Ftm2 => MAX_CHANNELS,
+        }
+    }
+
+    /// Check this timer has `channel`, and get its index.
+    fn channel_number(&self, channel: Channel) -> Result<usize, FtmError> {
+        let n = channel as usize;
+        if n < self.channel_count() {
+            Ok(n)
+        } else {
+            Err(FtmError::BadChannel)
+        }
+    }
+
+    /// The CnV value for a duty cycle. A value past the modulo gives
+    /// 100%.
+    fn channel_value(&self, duty: u16) -> u32 {
+        match self.alignment {
+            Alignment::Edge => ((self.modulo + 1) * duty as u32) / DUTY_MAX as u32,
+            Alignment::Center => (self.modulo * duty as u32) / DUTY_MAX as u32,
+        }
+    }
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+fn get_ftm_registers(id: FtmId) -> &'static mut reg::FtmRegisters {
+    match id {
+        FtmId::Ftm0 => reg::get_ftm0(),
+        FtmId::Ftm1 => reg::get_ftm1(),
+        FtmId::Ftm2 => reg::get_ftm2(),
+    }
+}
+
+/// The SYNC bit for a hardware trigger
+fn hardware_trigger_mask(trigger: HardwareTrigger) -> u32 {
+    match trigger {
+        HardwareTrigger::Trig0 => reg::FTM_SYNC_TRIG0,
+        HardwareTrigger::Trig1 => reg::FTM_SYNC_TRIG1,
+        HardwareTrigger::Trig2 => reg::FTM_SYNC_TRIG2,
+    }
+}
+
+/// Work out the smallest prescaler (as a power of two) and the modulo
+/// which get us closest to `frequency`. Out of range frequencies are
+/// clamped to the fastest or slowest we can do.
+fn calculate_timing(frequency: u32, alignment: Alignment) -> (u32, u32) {
+    // Edge-aligned: period = MOD + 1 ticks, and MOD must leave room for
+    // a 100% CnV. Center-aligned: period = 2 * MOD ticks, MOD <= 0x7FFF.
+    let max_ticks = match alignment {
+        Alignment::Edge => 0xFFFF,
+        Alignment::Center => 0xFFFE,
+    };
+    let frequency = if frequency == 0 { 1 } else { frequency };
+    let mut prescale = 0;
+    let mut ticks = 0;
+    while prescale <= MAX_PRESCALE {
+        let clock = CLOCK_SPEED >> prescale;
+        ticks = (clock + (frequency / 2)) / frequency;
+        if ticks <= max_ticks {
+            break;
+        }
+        prescale += 1;
+    }
+    if prescale > MAX_PRESCALE {
+        prescale = MAX_PRESCALE;
+        ticks = max_ticks;
+    }
+    if ticks < 2 {
+        ticks = 2;
+    }
+    match alignment {
+        Alignment::Edge => (prescale, ticks - 1),
+        Alignment::Center => (prescale, ticks / 2),
+    }
+}
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
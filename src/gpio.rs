@@ -127,6 +127,14 @@ pub fn read(pinport: PinPort) -> Level {
     }
 }
 
+/// Turn on the input buffer for a pin. It's off at reset, and `read`
+/// returns `Level::Low` until it's on. This works while a peripheral is
+/// driving the pin, too.
+pub fn enable_input(pinport: PinPort) {
+    let (iport, mask) = get_internal(pinport);
+    iport.idr.modify(|x| x & !mask);
+}
+
 /// Re-configure the pinmuxing so that the given Uart appears
 /// on its normal set of pins.
 ///
@@ -0,0 +1,1268 @@
+//! # I2C for the KE06Z
+//!
+//! The KE06Z has two I2C modules. This driver runs them as a polled
+//! master, with 7-bit or 10-bit addressing, or as an interrupt-driven
+//! slave (SMBus target).
+//!
+//! A slave looks like a register map to the bus master. The first octet
+//! written after our address selects a register, and following octets
+//! are written to (or read from) consecutive registers via callbacks.
+//!
+//! A master can also queue transactions to be run entirely from the
+//! interrupt handler, back-to-back, so a burst of sensor register reads
+//! needs no CPU time between octets. See `I2c::enable_queue`.
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use core::mem;
+
+use super::cortex_m0::systick;
+use super::gpio::{self, Level, Pin, PinMode, PinPort};
+use super::registers as reg;
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+/// This chip has 2 I2C modules
+#[derive(PartialEq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum I2cId {
+    I2c0,
+    I2c1,
+}
+
+/// The bus clock rate
+#[derive(PartialEq, Clone, Copy)]
+pub enum Speed {
+    /// Standard mode, 100 kHz
+    Standard,
+    /// Fast mode, 400 kHz
+    Fast,
+}
+
+/// The address of a slave device
+#[derive(PartialEq, Clone, Copy)]
+pub enum Address {
+    /// A 7-bit address (not shifted - i.e. 0x00 to 0x7F)
+    SevenBit(u8),
+    /// A 10-bit address (0x000 to 0x3FF)
+    TenBit(u16),
+}
+
+/// The ways a transaction can fail
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum I2cError {
+    /// The slave did not acknowledge its address or our data
+    Nack,
+    /// Another master won the bus
+    ArbitrationLost,
+    /// An octet took too long
+    Timeout,
+    /// The bus stayed busy, even after trying to recover it
+    BusStuck,
+}
+
+/// Slave addressing and SMBus options
+#[derive(Clone, Copy)]
+pub struct SlaveConfig {
+    /// Our 7-bit address
+    pub address: u8,
+    /// If set, we also answer every address above `address` up to and
+    /// including this one
+    pub range_end: Option<u8>,
+    /// If set, a second 7-bit address we answer (e.g. the SMBus Device
+    /// Default Address)
+    pub secondary: Option<u8>,
+    /// Answer the general call address (0x00)
+    pub general_call: bool,
+    /// Detect SMBus clock low (25 ms) and clock high timeouts
+    pub smbus_timeouts: bool,
+    /// Check a Packet Error Code on writes, and append one to reads.
+    /// Written octets are then only passed to the write callback once
+    /// the PEC has been checked, at the stop. Up to `PEC_WRITE_LEN`
+    /// octets are held per transaction - any more are dropped.
+    pub pec: bool,
+}
+
+/// Things that happen to a slave which aren't register accesses
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SlaveEvent {
+    /// The master sent a stop at the end of a transaction addressed to
+    /// us
+    Stop,
+    /// The Packet Error Code on a write was wrong. The octets written
+    /// during that transaction have been discarded.
+    PecError,
+    /// SCL was held low for longer than the SMBus timeout. The slave
+    /// has reset, discarding the transaction in progress.
+    SclLowTimeout,
+    /// SCL was high and SDA low for longer than the SMBus timeout
+    SclHighTimeout,
+}
+
+/// The master is reading register `register` of the slave at address
+/// `addr`. Return `None` when there is no more data (if PEC is enabled,
+/// the PEC is sent next).
+pub type SlaveRead = fn(addr: u8, register: u8) -> Option<u8>;
+
+/// The master has written `value` to register `register` of the slave
+/// at address `addr`. General calls have an `addr` of zero.
+pub type SlaveWrite = fn(addr: u8, register: u8, value: u8);
+
+/// Something else has happened to the slave.
+pub type SlaveEventHandler = fn(id: I2cId, event: SlaveEvent);
+
+/// The callbacks a slave uses, all called from interrupt context
+#[derive(Clone, Copy)]
+pub struct SlaveCallbacks {
+    /// Called for each octet the master reads
+    pub read: SlaveRead,
+    /// Called for each octet the master writes
+    pub write: SlaveWrite,
+    /// Called on stops, errors and timeouts
+    pub event: SlaveEventHandler,
+}
+
+/// A transaction for the interrupt-driven queue. Writes `write` (if
+/// not empty) to the slave at 7-bit address `addr`, then reads into
+/// `read` (if not empty), with a repeated start in between.
+pub struct Request {
+    /// The 7-bit slave address
+    pub addr: u8,
+    /// Octets to write, typically a register number
+    pub write: &'static [u8],
+    /// Where to put the octets read
+    pub read: &'static mut [u8],
+}
+
+/// Identifies a queued `Request`
+pub type Ticket = usize;
+
+/// Called from interrupt context when a queued request completes (or
+/// from `submit` or `take_result`, if it timed out).
+pub type QueueCallback = fn(id: I2cId, ticket: Ticket, result: Result<(), I2cError>);
+
+/// Controls a single I2C module as a master or a slave
+pub struct I2c {
+    id: I2cId,
+    timeout: usize,
+    scl: PinPort,
+    sda: PinPort,
+    reg: &'static mut reg::I2cRegisters,
+}
+
+// ****************************************************************************
+//
+// Private Types
+//
+// ****************************************************************************
+
+/// The direction of a transfer, as sent in the address octet
+#[derive(PartialEq, Clone, Copy)]
+enum Direction {
+    Write,
+    Read,
+}
+
+/// The state of a slave, shared with the interrupt handler
+struct SlaveState {
+    callbacks: SlaveCallbacks,
+    pec_enabled: bool,
+    /// We've been addressed since the transaction started
+    addressed: bool,
+    /// The address the master is talking to
+    addr: u8,
+    /// The register the next octet is read from or written to
+    register: u8,
+    /// The next octet written is a register number
+    awaiting_register: bool,
+    /// With PEC, we hold back each written octet until we know it isn't
+    /// the PEC itself
+    held: Option<u8>,
+    /// The read callback has run out of data
+    read_done: bool,
+    /// The PEC has been sent
+    pec_sent: bool,
+    /// The running PEC of this transaction, excluding the held octet
+    pec: u8,
+    /// With PEC, the (address, register, value) of each octet written
+    /// this transaction, waiting for the PEC to be checked
+    pending: [(u8, u8, u8); PEC_WRITE_LEN],
+    pending_len: usize,
+}
+
+/// A slot in the request queue
+enum Slot {
+    Free,
+    Queued(Request),
+    Active(Request),
+    Done(Request, Result<(), I2cError>),
+}
+
+/// Where the interrupt handler is in the active request
+#[derive(PartialEq, Clone, Copy)]
+enum Phase {
+    /// Sent the write address or a data octet
+    Write,
+    /// Sent the read address
+    ReadAddress,
+    /// Receiving
+    Read,
+}
+
+/// The request queue for a master, shared with the interrupt handler
+struct QueueState {
+    slots: [Slot; QUEUE_LEN],
+    /// The next slot to fill
+    tail: usize,
+    /// The slot being run, if any
+    active: Option<usize>,
+    phase: Phase,
+    /// How far through the write or read buffer we are
+    pos: usize,
+    /// When the active request last moved on, in SysTick ticks
+    progress: u64,
+    callback: Option<QueueCallback>,
+}
+
+// ****************************************************************************
+//
+// Public Data
+//
+// ****************************************************************************
+
+/// How many requests can be queued (or completed but not collected)
+pub const QUEUE_LEN: usize = 8;
+
+/// How many written octets a PEC-checking slave holds per transaction
+/// (an SMBus block write: register, count and 32 octets of data)
+pub const PEC_WRITE_LEN: usize = 34;
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+/// The Freedom board has an 8 MHz external oscillator
+/// We set the bus clock to 20 MHz
+const CLOCK_SPEED: u32 = 20_000_000;
+
+/// 10 ms, in SysTick ticks
+const DEFAULT_TIMEOUT: usize = 10 * systick::TICKS_PER_MS;
+
+/// Half an SCL period at 100 kHz, in SysTick ticks
+const RECOVERY_HALF_PERIOD: u64 = (systick::CLOCK_HZ / 200_000) as u64;
+
+/// The most clocks a slave can need to finish sending an octet (and get
+/// an ACK/NACK) and let go of SDA
+const RECOVERY_CLOCKS: usize = 9;
+
+/// The SMBus clock low timeout (25 ms), in units of the bus clock / 64
+const SMBUS_LOW_TIMEOUT: u16 = (CLOCK_SPEED / 64 / 40) as u16;
+
+/// What a slave sends once it runs out of data
+const SLAVE_FILL: u8 = 0xFF;
+
+/// Slave state for I2C0 and I2C1. Only touched by the interrupt
+/// handler while the I2C interrupt is enabled.
+static mut SLAVE_STATE: [Option<SlaveState>; 2] = [None, None];
+
+/// Master request queues for I2C0 and I2C1. Only touched by the
+/// interrupt handler while the I2C interrupt is enabled.
+static mut QUEUE_STATE: [Option<QueueState>; 2] = [None, None];
+
+/// SCL divider for each value of the ICR field
+const SCL_DIVIDERS: [u16; 64] = [20, 22, 24, 26, 28, 30, 34, 40, 28, 32, 36, 40, 44, 48, 56, 68,
+                                 48, 56, 64, 72, 80, 88, 104, 128, 80, 96, 112, 128, 144, 160,
+                                 192, 240, 160, 192, 224, 256, 288, 320, 384, 480, 320, 384, 448,
+                                 512, 576, 640, 768, 960, 640, 768, 896, 1024, 1152, 1280, 1536,
+                                 1920, 1280, 1536, 1792, 2048, 2304, 2560, 3072, 3840];
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+/// Represents a single I2C module on the KE06Z
+impl I2c {
+    /// Create a new I2c object as a bus master. The caller is responsible
+    /// for ensuring that only one object exists per I2cId.
+    pub fn new(id: I2cId, speed: Speed) -> I2c {
+        let mut i2c = I2c {
+            id: id,
+            timeout: DEFAULT_TIMEOUT,
+            scl: default_scl(id),
+            sda: default_sda(id),
+            reg: get_i2c_registers(id),
+        };
+
+        // Enable the I2C peripheral
+        reg::get_sim().scgc.modify(|x| {
+            x |
+            match id {
+                I2cId::I2c0 => reg::SIM_SCGC_I2C0,
+                I2cId::I2c1 => reg::SIM_SCGC_I2C1,
+            }
+        });
+
+        i2c.enable_line_inputs();
+
+        // Stop it while we configure it
+        i2c.reg.c1.write(0);
+        i2c.set_speed(speed);
+
+        // Turn it back on
+        i2c.reg.c1.write(reg::I2C_C1_IICEN);
+
+        i2c
+    }
+
+    /// Create a new I2c object as a slave. The caller is responsible
+    /// for ensuring that only one object exists per I2cId, and that this
+    /// module's interrupt handler (`i2c0_isr` or `i2c1_isr`) is in the
+    /// vector table and enabled.
+    pub fn new_slave(id: I2cId, config: &SlaveConfig, callbacks: SlaveCallbacks) -> I2c {
+        let i2c = I2c {
+            id: id,
+            timeout: DEFAULT_TIMEOUT,
+            scl: default_scl(id),
+            sda: default_sda(id),
+            reg: get_i2c_registers(id),
+        };
+
+        // Enable the I2C peripheral
+        reg::get_sim().scgc.modify(|x| {
+            x |
+            match id {
+                I2cId::I2c0 => reg::SIM_SCGC_I2C0,
+                I2cId::I2c1 => reg::SIM_SCGC_I2C1,
+            }
+        });
+
+        i2c.enable_line_inputs();
+
+        // Stop it while we configure it
+        i2c.reg.c1.write(0);
+
+        unsafe {
+            SLAVE_STATE[id as usize] = Some(SlaveState {
+                callbacks: callbacks,
+                pec_enabled: config.pec,
+                addressed: false,
+                addr: 0,
+                register: 0,
+                awaiting_register: true,
+                held: None,
+                read_done: false,
+                pec_sent: false,
+                pec: 0,
+                pending: [(0, 0, 0); PEC_WRITE_LEN],
+                pending_len: 0,
+            });
+        }
+
+        i2c.reg.a1.write(config.address << 1);
+        let mut c2 = 0;
+        if config.general_call {
+            c2 |= reg::I2C_C2_GCAEN;
+        }
+        if let Some(end) = config.range_end {
+            i2c.reg.ra.write(end << 1);
+            c2 |= reg::I2C_C2_RMEN;
+        }
+        i2c.reg.c2.write(c2);
+
+        let mut smb = 0;
+        if let Some(addr) = config.secondary {
+            i2c.reg.a2.write(addr << 1);
+            smb |= reg::I2C_SMB_SIICAEN;
+        }
+        if config.smbus_timeouts {
+            i2c.reg.slth.write((SMBUS_LOW_TIMEOUT >> 8) as u8);
+            i2c.reg.sltl.write(SMBUS_LOW_TIMEOUT as u8);
+            smb |= reg::I2C_SMB_SHTF2IE;
+        } else {
+            i2c.reg.slth.write(0);
+            i2c.reg.sltl.write(0);
+        }
+        i2c.reg.smb.write(smb);
+
+        // Interrupt on stop, so we know when a transaction has finished
+        i2c.reg.flt.modify(|x| x | reg::I2C_FLT_SSIE | reg::I2C_FLT_STOPF | reg::I2C_FLT_STARTF);
+
+        // Turn it back on, with interrupts
+        i2c.reg.c1.write(reg::I2C_C1_IICEN | reg::I2C_C1_IICIE);
+
+        i2c
+    }
+
+    /// Set the clock rate. Picks the fastest rate which does not exceed
+    /// the one asked for, and returns it.
+    pub fn set_speed(&mut self, speed: Speed) -> u32 {
+        let baud = match speed {
+            Speed::Standard => 100_000,
+            Speed::Fast => 400_000,
+        };
+        let (mult, icr, actual) = calculate_divider(baud);
+        self.reg.f.write(reg::i2c_f_mult(mult) | reg::i2c_f_icr(icr));
+        actual
+    }
+
+    /// Set how long to wait for the bus to be free, or for each octet to
+    /// be transferred, in SysTick ticks. A queued request which makes no
+    /// progress for this long fails with `I2cError::Timeout` - this is
+    /// checked whenever `submit` or `take_result` is called.
+    pub fn set_timeout(&mut self, timeout: usize) {
+        self.timeout = timeout;
+    }
+
+    /// Start using the interrupt-driven request queue. Requests given to
+    /// `submit` are run one after the other from the interrupt handler
+    /// (`i2c0_isr` or `i2c1_isr`, which must be in the vector table and
+    /// enabled). If given, `callback` is called as each one completes -
+    /// otherwise poll with `take_result`. Don't use the blocking
+    /// functions while the queue is in use.
+    ///
+    /// The stop interrupt is enabled too, so a request queued while the
+    /// bus is busy starts once the bus is free.
+    pub fn enable_queue(&mut self, callback: Option<QueueCallback>) {
+        self.reg.c1.modify(|x| x & !reg::I2C_C1_IICIE);
+        unsafe {
+            QUEUE_STATE[self.id as usize] = Some(QueueState {
+                slots: [Slot::Free, Slot::Free, Slot::Free, Slot::Free, Slot::Free, Slot::Free,
+                        Slot::Free, Slot::Free],
+                tail: 0,
+                active: None,
+                phase: Phase::Write,
+                pos: 0,
+                progress: 0,
+                callback: callback,
+            });
+        }
+        // STOPF and STARTF are cleared by writing a one
+        self.reg.flt.modify(|x| x | reg::I2C_FLT_SSIE | reg::I2C_FLT_STOPF | reg::I2C_FLT_STARTF);
+        self.reg.c1.modify(|x| x | reg::I2C_C1_IICIE);
+    }
+
+    /// Stop using the request queue. Anything still queued is dropped.
+    pub fn disable_queue(&mut self) {
+        self.reg.c1.modify(|x| x & !reg::I2C_C1_IICIE);
+        self.reg.flt.modify(|x| x & !reg::I2C_FLT_SSIE);
+        self.stop();
+        unsafe {
+            QUEUE_STATE[self.id as usize] = None;
+        }
+    }
+
+    /// Queue a request. It starts straight away if the queue is idle and
+    /// the bus is free. If the queue is full (or not enabled), the request
+    /// is handed back.
+    pub fn submit(&mut self, request: Request) -> Result<Ticket, Request> {
+        let enabled = self.mask_queue();
+        let result = match unsafe { &mut QUEUE_STATE[self.id as usize] } {
+            &mut Some(ref mut queue) => {
+                check_queue_timeout(self.id, self.reg, queue, self.timeout);
+                let ticket = queue.tail;
+                match queue.slots[ticket] {
+                    Slot::Free => {
+                        queue.slots[ticket] = Slot::Queued(request);
+                        queue.tail = (queue.tail + 1) % QUEUE_LEN;
+                        if queue.active.is_none() {
+                            start_next(self.reg, queue);
+                        }
+                        Ok(ticket)
+                    }
+                    _ => Err(request),
+                }
+            }
+            &mut None => Err(request),
+        };
+        self.unmask_queue(enabled);
+        result
+    }
+
+    /// Collect a completed request. Returns `None` if it hasn't finished
+    /// yet, or the request (with its read buffer filled) and the result.
+    /// Its slot in the queue is then free for re-use.
+    pub fn take_result(&mut self, ticket: Ticket) -> Option<(Request, Result<(), I2cError>)> {
+        let enabled = self.mask_queue();
+        let result = match unsafe { &mut QUEUE_STATE[self.id as usize] } {
+            &mut Some(ref mut queue) if ticket < QUEUE_LEN => {
+                check_queue_timeout(self.id, self.reg, queue, self.timeout);
+                match mem::replace(&mut queue.slots[ticket], Slot::Free) {
+                    Slot::Done(request, result) => Some((request, result)),
+                    other => {
+                        queue.slots[ticket] = other;
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        self.unmask_queue(enabled);
+        result
+    }
+
+    /// Abandon a request which hasn't been collected, and hand it back.
+    /// If it's running, it's cut short with a stop and the next request
+    /// starts; the callback isn't called. Returns `None` if there's no
+    /// such request.
+    pub fn cancel(&mut self, ticket: Ticket) -> Option<Request> {
+        let enabled = self.mask_queue();
+        let result = match unsafe { &mut QUEUE_STATE[self.id as usize] } {
+            &mut Some(ref mut queue) if ticket < QUEUE_LEN => {
+                let request = match mem::replace(&mut queue.slots[ticket], Slot::Free) {
+                    Slot::Free => None,
+                    Slot::Queued(request) |
+                    Slot::Active(request) |
+                    Slot::Done(request, _) => Some(request),
+                };
+                if queue.active == Some(ticket) {
+                    queue.active = None;
+                    self.stop();
+                    start_next(self.reg, queue);
+                }
+                request
+            }
+            _ => None,
+        };
+        self.unmask_queue(enabled);
+        result
+    }
+
+    /// Returns true if nothing is queued or running.
+    pub fn queue_idle(&self) -> bool {
+        match unsafe { &QUEUE_STATE[self.id as usize] } {
+            &Some(ref queue) => queue.active.is_none(),
+            &None => true,
+        }
+    }
+
+    /// Tell the driver which pins SCL and SDA are on, for stuck-bus
+    /// detection and recovery.
+    /// Only needed if the I2C pins have been moved with SIM_PINSEL.
+    pub fn set_pins(&mut self, scl: PinPort, sda: PinPort) {
+        self.scl = scl;
+        self.sda = sda;
+        self.enable_line_inputs();
+    }
+
+    /// Returns true if the bus is busy but we are not the master - e.g.
+    /// because a slave is holding SDA low after a brown-out. BUSY only
+    /// follows the starts and stops the module has seen, so SDA and SCL
+    /// are checked as well.
+    pub fn bus_stuck(&self) -> bool {
+        (self.reg.c1.read() & reg::I2C_C1_MST) == 0 &&
+        ((self.reg.s.read() & reg::I2C_S_BUSY) != 0 || !self.lines_released())
+    }
+
+    /// Try to free a stuck bus. The module is disabled so the pins fall
+    /// back to GPIO, then SCL is clocked (up to nine times) until the
+    /// slave lets go of SDA, and a stop is generated. The module is then
+    /// re-enabled.
+    ///
+    /// The transfer functions do this automatically if the bus is busy (or
+    /// SDA or SCL is low) for too long, before a transfer or after one
+    /// times out or loses arbitration. This must not be used while the
+    /// module is a slave.
+    pub fn recover_bus(&mut self) -> Result<(), I2cError> {
+        let c1 = self.reg.c1.read();
+        self.reg.c1.write(0);
+        self.enable_line_inputs();
+
+        // Open drain, by switching between input and output-low
+        release(self.scl);
+        release(self.sda);
+        half_period();
+
+        for _ in 0..RECOVERY_CLOCKS {
+            if gpio::read(self.sda) == Level::High {
+                break;
+            }
+            drive_low(self.scl);
+            half_period();
+            release(self.scl);
+            half_period();
+        }
+
+        // Stop: SDA goes high while SCL is high
+        drive_low(self.scl);
+        half_period();
+        drive_low(self.sda);
+        half_period();
+        release(self.scl);
+        half_period();
+        release(self.sda);
+        half_period();
+
+        let free = self.lines_released();
+
+        // Give the pins back to the module
+        self.reg.c1.write(c1 & !(reg::I2C_C1_MST | reg::I2C_C1_TX | reg::I2C_C1_TXAK));
+
+        if free {
+            Ok(())
+        } else {
+            Err(I2cError::BusStuck)
+        }
+    }
+
+    /// Write `data` to the slave at `addr`.
+    pub fn write(&mut self, addr: Address, data: &[u8]) -> Result<(), I2cError> {
+        self.transaction(addr, data, &mut [])
+    }
+
+    /// Read `buffer.len()` octets from the slave at `addr`.
+    pub fn read(&mut self, addr: Address, buffer: &mut [u8]) -> Result<(), I2cError> {
+        self.transaction(addr, &[], buffer)
+    }
+
+    /// Write `data` to the slave at `addr`, then without releasing the bus
+    /// (i.e. with a repeated start), read `buffer.len()` octets back.
+    /// Typically used to read registers.
+    pub fn write_read(&mut self,
+                      addr: Address,
+                      data: &[u8],
+                      buffer: &mut [u8])
+                      -> Result<(), I2cError> {
+        self.transaction(addr, data, buffer)
+    }
+
+    /// Run a complete transaction, always leaving the bus released.
+    fn transaction(&mut self,
+                   addr: Address,
+                   data: &[u8],
+                   buffer: &mut [u8])
+                   -> Result<(), I2cError> {
+        if self.wait_bus_free().is_err() {
+            self.recover_bus()?;
+            self.wait_bus_free().map_err(|_| I2cError::BusStuck)?;
+        }
+        let result = self.transfer(addr, data, buffer);
+        self.stop();
+        match result {
+            Err(I2cError::Timeout) |
+            Err(I2cError::ArbitrationLost) => {
+                // A slave may have been left holding a line low
+                if self.wait_bus_free().is_err() {
+                    let _ = self.recover_bus();
+                }
+            }
+            _ => {}
+        }
+        result
+    }
+
+    /// Generate a start, then write and/or read.
+    fn transfer(&mut self, addr: Address, data: &[u8], buffer: &mut [u8]) -> Result<(), I2cError> {
+        // Setting MST generates a start
+        self.reg.c1.modify(|x| x | reg::I2C_C1_TX);
+        self.reg.c1.modify(|x| x | reg::I2C_C1_MST);
+        let mut addressed = false;
+        if !data.is_empty() || buffer.is_empty() {
+            self.send_address(addr, Direction::Write, addressed)?;
+            addressed = true;
+            for &b in data {
+                self.send_octet(b)?;
+            }
+            if !buffer.is_empty() {
+                self.repeated_start();
+            }
+        }
+        if !buffer.is_empty() {
+            self.send_address(addr, Direction::Read, addressed)?;
+            self.receive(buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Send the address octet(s). A 10-bit read needs the full address
+    /// written first, so if that hasn't happened (`addressed` is false)
+    /// we do it here.
+    fn send_address(&mut self,
+                    addr: Address,
+                    direction: Direction,
+                    addressed: bool)
+                    -> Result<(), I2cError> {
+        let rw = match direction {
+            Direction::Write => 0,
+            Direction::Read => 1,
+        };
+        match addr {
+            Address::SevenBit(a) => self.send_octet((a << 1) | rw),
+            Address::TenBit(a) => {
+                let hi = 0xF0 | (((a >> 7) as u8) & 0x06);
+                if direction == Direction::Write || !addressed {
+                    self.send_octet(hi)?;
+                    self.send_octet(a as u8)?;
+                }
+                if direction == Direction::Read {
+                    if !addressed {
+                        self.repeated_start();
+                    }
+                    self.send_octet(hi | rw)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Send one octet and check it was acknowledged.
+    fn send_octet(&mut self, value: u8) -> Result<(), I2cError> {
+        self.reg.d.write(value);
+        self.wait_transfer()?;
+        if (self.reg.s.read() & reg::I2C_S_RXAK) != 0 {
+            Err(I2cError::Nack)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Receive into `buffer`, acknowledging every octet but the last. We
+    /// generate the stop before reading the last octet from the data
+    /// register, as reading it would start another transfer.
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<(), I2cError> {
+        let len = buffer.len();
+        self.reg.c1.modify(|x| x & !reg::I2C_C1_TX);
+        if len == 1 {
+            self.reg.c1.modify(|x| x | reg::I2C_C1_TXAK);
+        } else {
+            self.reg.c1.modify(|x| x & !reg::I2C_C1_TXAK);
+        }
+        // A dummy read starts the first transfer
+        let _ = self.reg.d.read();
+        for (idx, b) in buffer.iter_mut().enumerate() {
+            self.wait_transfer()?;
+            if idx == len - 1 {
+                self.stop();
+            } else if idx == len - 2 {
+                self.reg.c1.modify(|x| x | reg::I2C_C1_TXAK);
+            }
+            *b = self.reg.d.read();
+        }
+        Ok(())
+    }
+
+    /// Generate a repeated start. We must be the master, transmitting.
+    fn repeated_start(&mut self) {
+        self.reg.c1.modify(|x| x | reg::I2C_C1_TX | reg::I2C_C1_RSTA);
+    }
+
+    /// Generate a stop (if we're the master) and go back to idle.
+    fn stop(&mut self) {
+        self.reg.c1.modify(|x| x & !(reg::I2C_C1_MST | reg::I2C_C1_TX | reg::I2C_C1_TXAK));
+    }
+
+    /// Keep the interrupt handler out while we change the queue. Returns
+    /// whether the interrupt was enabled, for `unmask_queue`.
+    fn mask_queue(&mut self) -> bool {
+        let enabled = (self.reg.c1.read() & reg::I2C_C1_IICIE) != 0;
+        self.reg.c1.modify(|x| x & !reg::I2C_C1_IICIE);
+        enabled
+    }
+
+    /// Undo `mask_queue`.
+    fn unmask_queue(&mut self, enabled: bool) {
+        if enabled {
+            self.reg.c1.modify(|x| x | reg::I2C_C1_IICIE);
+        }
+    }
+
+    /// Let us read SDA and SCL, whoever is driving them.
+    fn enable_line_inputs(&self) {
+        gpio::enable_input(self.scl);
+        gpio::enable_input(self.sda);
+    }
+
+    /// Returns true if nothing is holding SDA or SCL low.
+    fn lines_released(&self) -> bool {
+        gpio::read(self.sda) == Level::High && gpio::read(self.scl) == Level::High
+    }
+
+    /// Wait for the bus to be free, with both lines high.
+    fn wait_bus_free(&mut self) -> Result<(), I2cError> {
+        let start = systick::run_time_ticks();
+        while (self.reg.s.read() & reg::I2C_S_BUSY) != 0 || !self.lines_released() {
+            if (systick::run_time_ticks() - start) >= (self.timeout as u64) {
+                return Err(I2cError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for the current octet to be transferred, and clear the
+    /// interrupt flag.
+    fn wait_transfer(&mut self) -> Result<(), I2cError> {
+        let start = systick::run_time_ticks();
+        while (self.reg.s.read() & reg::I2C_S_IICIF) == 0 {
+            if (systick::run_time_ticks() - start) >= (self.timeout as u64) {
+                return Err(I2cError::Timeout);
+            }
+        }
+        // IICIF and ARBL are cleared by writing a one
+        self.reg.s.write(reg::I2C_S_IICIF);
+        if (self.reg.s.read() & reg::I2C_S_ARBL) != 0 {
+            self.reg.s.write(reg::I2C_S_ARBL);
+            return Err(I2cError::ArbitrationLost);
+        }
+        Ok(())
+    }
+}
+
+/// Called when I2C 0 interrupt fires
+pub unsafe extern "C" fn i2c0_isr() {
+    service_queue(I2cId::I2c0);
+    service_slave(I2cId::I2c0);
+}
+
+/// Called when I2C 1 interrupt fires
+pub unsafe extern "C" fn i2c1_isr() {
+    service_queue(I2cId::I2c1);
+    service_slave(I2cId::I2c1);
+}
+
+/// Calculate the SMBus Packet Error Code (CRC-8, polynomial
+/// x^8 + x^2 + x + 1) of `data`, continuing from `crc`. Start from zero.
+pub fn pec_update(crc: u8, data: &[u8]) -> u8 {
+    let mut crc = crc;
+    for &b in data {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if (crc & 0x80) != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+/// Get a reference to the I2C control register struct in the chip.
+fn get_i2c_registers(i2c_id: I2cId) -> &'static mut reg::I2cRegisters {
+    match i2c_id {
+        I2cId::I2c0 => reg::get_i2c0(),
+        I2cId::I2c1 => reg::get_i2c1(),
+    }
+}
+
+/// The default SCL pin for each module
+fn default_scl(id: I2cId) -> PinPort {
+    match id {
+        I2cId::I2c0 => PinPort::PortA(Pin::Pin3),
+        I2cId::I2c1 => PinPort::PortE(Pin::Pin1),
+    }
+}
+
+/// The default SDA pin for each module
+fn default_sda(id: I2cId) -> PinPort {
+    match id {
+        I2cId::I2c0 => PinPort::PortA(Pin::Pin2),
+        I2cId::I2c1 => PinPort::PortE(Pin::Pin0),
+    }
+}
+
+/// Pull a bus line low.
+fn drive_low(pin: PinPort) {
+    gpio::set_direction(pin, PinMode::Output);
+    gpio::set(pin, Level::Low);
+}
+
+/// Let a bus line float high.
+fn release(pin: PinPort) {
+    gpio::set_direction(pin, PinMode::InputPull(Level::High));
+}
+
+/// Busy-wait for half an SCL period.
+fn half_period() {
+    let start = systick::run_time_ticks();
+    while (systick::run_time_ticks() - start) < RECOVERY_HALF_PERIOD {}
+}
+
+/// Start the oldest queued request, if there is one, nothing is running
+/// and the bus is free. Otherwise the stop interrupt tries again.
+fn start_next(i2c: &mut reg::I2cRegisters, queue: &mut QueueState) {
+    if queue.active.is_some() || (i2c.s.read() & reg::I2C_S_BUSY) != 0 {
+        return;
+    }
+    // The oldest queued request is the first one after the last slot
+    // we filled.
+    let next = (0..QUEUE_LEN)
+        .map(|n| (queue.tail + n) % QUEUE_LEN)
+        .find(|&idx| match queue.slots[idx] {
+            Slot::Queued(_) => true,
+            _ => false,
+        });
+    let idx = match next {
+        Some(idx) => idx,
+        None => return,
+    };
+    let request = match mem::replace(&mut queue.slots[idx], Slot::Free) {
+        Slot::Queued(request) => request,
+        _ => return,
+    };
+    // Setting MST generates a start
+    i2c.c1.modify(|x| x | reg::I2C_C1_TX);
+    i2c.c1.modify(|x| x | reg::I2C_C1_MST);
+    if request.write.is_empty() && !request.read.is_empty() {
+        queue.phase = Phase::ReadAddress;
+        i2c.d.write((request.addr << 1) | 1);
+    } else {
+        queue.phase = Phase::Write;
+        i2c.d.write(request.addr << 1);
+    }
+    queue.pos = 0;
+    queue.progress = systick::run_time_ticks();
+    queue.slots[idx] = Slot::Active(request);
+    queue.active = Some(idx);
+}
+
+/// Finish the active request, and start the next.
+fn finish_active(id: I2cId,
+                 i2c: &mut reg::I2cRegisters,
+                 queue: &mut QueueState,
+                 result: Result<(), I2cError>) {
+    i2c.c1.modify(|x| x & !(reg::I2C_C1_MST | reg::I2C_C1_TX | reg::I2C_C1_TXAK));
+    if let Some(idx) = queue.active.take() {
+        if let Slot::Active(request) = mem::replace(&mut queue.slots[idx], Slot::Free) {
+            queue.slots[idx] = Slot::Done(request, result);
+        }
+        if let Some(callback) = queue.callback {
+            callback(id, idx, result);
+        }
+    }
+    start_next(i2c, queue);
+}
+
+/// Give up on the active request if it has made no progress for
+/// `timeout` ticks. Otherwise, start the next request if the bus has
+/// come free without us noticing.
+fn check_queue_timeout(id: I2cId,
+                       i2c: &mut reg::I2cRegisters,
+                       queue: &mut QueueState,
+                       timeout: usize) {
+    if queue.active.is_some() {
+        if (systick::run_time_ticks() - queue.progress) >= (timeout as u64) {
+            finish_active(id, i2c, queue, Err(I2cError::Timeout));
+        }
+    } else {
+        start_next(i2c, queue);
+    }
+}
+
+/// Run the master request queue state machine.
+unsafe fn service_queue(id: I2cId) {
+    let i2c = get_i2c_registers(id);
+    let queue = match QUEUE_STATE[id as usize] {
+        Some(ref mut queue) => queue,
+        None => return,
+    };
+
+    let s = i2c.s.read();
+    if (s & reg::I2C_S_IICIF) == 0 {
+        return;
+    }
+    i2c.s.write(reg::I2C_S_IICIF);
+
+    // Stop (and start) detection. STOPF and STARTF are cleared by
+    // writing a one.
+    let flt = i2c.flt.read();
+    i2c.flt.write(flt | reg::I2C_FLT_STOPF | reg::I2C_FLT_STARTF);
+
+    if (s & reg::I2C_S_ARBL) != 0 {
+        i2c.s.write(reg::I2C_S_ARBL);
+        if queue.active.is_some() {
+            finish_active(id, i2c, queue, Err(I2cError::ArbitrationLost));
+        }
+        return;
+    }
+
+    let idx = match queue.active {
+        Some(idx) => idx,
+        None => {
+            // The bus may be free now
+            if (flt & reg::I2C_FLT_STOPF) != 0 {
+                start_next(i2c, queue);
+            }
+            return;
+        }
+    };
+
+    // A start or stop on the bus, rather than the end of an octet
+    if (s & reg::I2C_S_TCF) == 0 {
+        return;
+    }
+    queue.progress = systick::run_time_ticks();
+
+    let result = match queue.slots[idx] {
+        Slot::Active(ref mut request) => {
+            match queue.phase {
+                Phase::Write => {
+                    if (s & reg::I2C_S_RXAK) != 0 {
+                        Some(Err(I2cError::Nack))
+                    } else if queue.pos < request.write.len() {
+                        i2c.d.write(request.write[queue.pos]);
+                        queue.pos += 1;
+                        None
+                    } else if !request.read.is_empty() {
+                        i2c.c1.modify(|x| x | reg::I2C_C1_RSTA);
+                        i2c.d.write((request.addr << 1) | 1);
+                        queue.phase = Phase::ReadAddress;
+                        None
+                    } else {
+                        Some(Ok(()))
+                    }
+                }
+                Phase::ReadAddress => {
+                    if (s & reg::I2C_S_RXAK) != 0 {
+                        Some(Err(I2cError::Nack))
+                    } else {
+                        i2c.c1.modify(|x| x & !reg::I2C_C1_TX);
+                        if request.read.len() == 1 {
+                            i2c.c1.modify(|x| x | reg::I2C_C1_TXAK);
+                        } else {
+                            i2c.c1.modify(|x| x & !reg::I2C_C1_TXAK);
+                        }
+                        queue.phase = Phase::Read;
+                        queue.pos = 0;
+                        // A dummy read starts the first transfer
+                        let _ = i2c.d.read();
+                        None
+                    }
+                }
+                Phase::Read => {
+                    let len = request.read.len();
+                    if queue.pos == len - 1 {
+                        // Stop before reading the last octet, or reading
+                        // it would start another transfer
+                        i2c.c1.modify(|x| x & !reg::I2C_C1_MST);
+                        request.read[queue.pos] = i2c.d.read();
+                        Some(Ok(()))
+                    } else {
+                        if queue.pos == len - 2 {
+                            i2c.c1.modify(|x| x | reg::I2C_C1_TXAK);
+                        }
+                        request.read[queue.pos] = i2c.d.read();
+                        queue.pos += 1;
+                        None
+                    }
+                }
+            }
+        }
+        _ => Some(Err(I2cError::Timeout)),
+    };
+
+    if let Some(result) = result {
+        finish_active(id, i2c, queue, result);
+    }
+}
+
+/// Run the slave state machine. This follows the interrupt routine
+/// flowchart in the reference manual.
+unsafe fn service_slave(id: I2cId) {
+    let i2c = get_i2c_registers(id);
+    let state = match SLAVE_STATE[id as usize] {
+        Some(ref mut state) => state,
+        None => return,
+    };
+
+    // Stop (and start) detection. STOPF and STARTF are cleared by
+    // writing a one.
+    let flt = i2c.flt.read();
+    if (flt & reg::I2C_FLT_STOPF) != 0 {
+        i2c.flt.write(flt | reg::I2C_FLT_STOPF | reg::I2C_FLT_STARTF);
+        i2c.s.write(reg::I2C_S_IICIF);
+        slave_stop(id, state);
+        return;
+    }
+    if (flt & reg::I2C_FLT_STARTF) != 0 {
+        i2c.flt.write((flt & !reg::I2C_FLT_STOPF) | reg::I2C_FLT_STARTF);
+        i2c.s.write(reg::I2C_S_IICIF);
+        return;
+    }
+
+    // SMBus timeouts. SLTF and SHTF2 are cleared by writing a one.
+    let smb = i2c.smb.read();
+    if (smb & reg::I2C_SMB_SLTF) != 0 {
+        i2c.smb.write((smb & !reg::I2C_SMB_SHTF2) | reg::I2C_SMB_SLTF);
+        i2c.s.write(reg::I2C_S_IICIF);
+        // SMBus says we must reset our interface. Turning the module off
+        // and on again lets go of SCL and SDA.
+        let c1 = i2c.c1.read() & !(reg::I2C_C1_TX | reg::I2C_C1_TXAK);
+        i2c.c1.write(c1 & !reg::I2C_C1_IICEN);
+        i2c.c1.write(c1);
+        slave_reset(state);
+        (state.callbacks.event)(id, SlaveEvent::SclLowTimeout);
+        return;
+    }
+    if (smb & reg::I2C_SMB_SHTF2) != 0 {
+        i2c.smb.write((smb & !reg::I2C_SMB_SLTF) | reg::I2C_SMB_SHTF2);
+        i2c.s.write(reg::I2C_S_IICIF);
+        (state.callbacks.event)(id, SlaveEvent::SclHighTimeout);
+        return;
+    }
+
+    let s = i2c.s.read();
+    if (s & reg::I2C_S_IICIF) == 0 {
+        return;
+    }
+    i2c.s.write(reg::I2C_S_IICIF);
+
+    if (s & reg::I2C_S_ARBL) != 0 {
+        i2c.s.write(reg::I2C_S_ARBL);
+        if (s & reg::I2C_S_IAAS) == 0 {
+            return;
+        }
+    }
+
+    if (s & reg::I2C_S_IAAS) != 0 {
+        // We've been addressed. The data register holds the address octet.
+        let addr_octet = i2c.d.read();
+        state.addressed = true;
+        // A repeated start means any octet we held back wasn't the PEC
+        if let Some(held) = state.held.take() {
+            state.pec = pec_update(state.pec, &[held]);
+            slave_write(state, held);
+        }
+        state.pec = pec_update(state.pec, &[addr_octet]);
+        state.addr = addr_octet >> 1;
+        if (s & reg::I2C_S_SRW) != 0 {
+            // Master is reading from us
+            state.read_done = false;
+            state.pec_sent = false;
+            i2c.c1.modify(|x| x | reg::I2C_C1_TX);
+            let value = slave_next_octet(state);
+            i2c.d.write(value);
+        } else {
+            // Master is writing to us
+            state.awaiting_register = true;
+            i2c.c1.modify(|x| x & !(reg::I2C_C1_TX | reg::I2C_C1_TXAK));
+        }
+    } else if (i2c.c1.read() & reg::I2C_C1_TX) != 0 {
+        if (s & reg::I2C_S_RXAK) != 0 {
+            // Master has had enough. Switch to receive and do a dummy
+            // read to release the bus.
+            i2c.c1.modify(|x| x & !reg::I2C_C1_TX);
+            let _ = i2c.d.read();
+        } else {
+            let value = slave_next_octet(state);
+            i2c.d.write(value);
+        }
+    } else {
+        let value = i2c.d.read();
+        if state.pec_enabled {
+            // Deliver the previous octet - this one might be the PEC
+            if let Some(held) = state.held {
+                state.pec = pec_update(state.pec, &[held]);
+                slave_write(state, held);
+            }
+            state.held = Some(value);
+        } else {
+            slave_write(state, value);
+        }
+    }
+}
+
+/// Get the next octet for the master to read.
+fn slave_next_octet(state: &mut SlaveState) -> u8 {
+    if !state.read_done {
+        if let Some(value) = (state.callbacks.read)(state.addr, state.register) {
+            state.register = state.register.wrapping_add(1);
+            state.pec = pec_update(state.pec, &[value]);
+            return value;
+        }
+        state.read_done = true;
+    }
+    if state.pec_enabled && !state.pec_sent {
+        state.pec_sent = true;
+        state.pec
+    } else {
+        SLAVE_FILL
+    }
+}
+
+/// Handle an octet written by the master. With PEC, it's held until
+/// the stop.
+fn slave_write(state: &mut SlaveState, value: u8) {
+    if state.awaiting_register {
+        state.register = value;
+        state.awaiting_register = false;
+    } else {
+        if !state.pec_enabled {
+            (state.callbacks.write)(state.addr, state.register, value);
+        } else if state.pending_len < PEC_WRITE_LEN {
+            state.pending[state.pending_len] = (state.addr, state.register, value);
+            state.pending_len += 1;
+        }
+        state.register = state.register.wrapping_add(1);
+    }
+}
+
+/// The master has sent a stop. If it was talking to us, check the PEC,
+/// if any, pass on the octets written if it's right, and report the
+/// stop. Then reset.
+fn slave_stop(id: I2cId, state: &mut SlaveState) {
+    if !state.addressed {
+        slave_reset(state);
+        return;
+    }
+    if let Some(held) = state.held.take() {
+        if held == state.pec {
+            for &(addr, register, value) in &state.pending[..state.pending_len] {
+                (state.callbacks.write)(addr, register, value);
+            }
+        } else {
+            (state.callbacks.event)(id, SlaveEvent::PecError);
+        }
+    }
+    slave_reset(state);
+    (state.callbacks.event)(id, SlaveEvent::Stop);
+}
+
+/// Forget the transaction in progress.
+fn slave_reset(state: &mut SlaveState) {
+    state.addressed = false;
+    state.held = None;
+    state.pec = 0;
+    state.pending_len = 0;
+    state.awaiting_register = true;
+    state.read_done = false;
+    state.pec_sent = false;
+}
+
+/// The I2C clock is the bus clock divided by the multiplier (1, 2 or 4)
+/// and the SCL divider selected by ICR. Find the fastest rate which does
+/// not exceed `baud`. Returns (MULT, ICR, rate).
+fn calculate_divider(baud: u32) -> (u8, u8, u32) {
+    let mut best = (2, 63, CLOCK_SPEED / (4 * 3840));
+    for mult in 0..3 {
+        for icr in 0..64 {
+            let rate = CLOCK_SPEED / ((1 << mult) * SCL_DIVIDERS[icr as usize] as u32);
+            if rate <= baud && rate > best.2 {
+                best = (mult, icr, rate);
+            }
+        }
+    }
+    best
+}
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
@@ -71,6 +71,30 @@ pub fn init() {
     nop();
     nop();
 
+    // Wait for it to settle and lock
+    wait_for_lock();
+
+    // core clock = ICSOUT/1; bus clock = core clock / 2
+    sim.clkdiv.write(0x01100000);
+
+    // bdiv = divide by 1
+    ics.c2.write(0x00);
+}
+
+/// Wait for the external oscillator to be running and the FLL to be
+/// locked to it.
+///
+/// The oscillator and FLL stop in Stop mode, so this must be called on
+/// wake-up before using any peripheral (like a UART) that depends on the
+/// bus clock being accurate.
+pub fn wait_for_lock() {
+    let ics = reg::get_ics();
+    let osc = reg::get_osc();
+
+    while (osc.cr.read() & reg::OSC_CR_OSCINIT) == 0 {
+
+    }
+
     // Wait for it to settle...
     while (ics.status.read() & reg::ICS_STATUS_IREFST) != 0 {
 
@@ -80,12 +104,6 @@ pub fn init() {
     while (ics.status.read() & reg::ICS_STATUS_LOCK) == 0 {
 
     }
-
-    // core clock = ICSOUT/1; bus clock = core clock / 2
-    sim.clkdiv.write(0x01100000);
-
-    // bdiv = divide by 1
-    ics.c2.write(0x00);
 }
 
 // ****************************************************************************
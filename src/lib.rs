@@ -22,6 +22,16 @@ pub mod sim;
 pub mod uart;
 pub mod ics;
 pub mod osc;
+pub mod lin;
+pub mod spi;
+pub mod spi_bus;
+pub mod i2c;
+pub mod adc;
+pub mod acmp;
+pub mod ftm;
+pub mod power;
+#[cfg(feature = "shell")]
+pub mod shell;
 
 use cortex_m::asm::nop;
 
@@ -0,0 +1,312 @@
+//! # LIN frame layer for the KE06Z
+//!
+//! Implements LIN 2.x master and slave frames on top of a `Uart`. The
+//! UART should be configured at the bus baud rate (typically 19200) and
+//! wired to a LIN transceiver, which echoes everything we transmit back
+//! to our receiver.
+//!
+//! A frame is a header (break, 0x55 sync, protected identifier) sent by
+//! the master, followed by a response of up to eight data octets and a
+//! checksum, sent by whichever node publishes that identifier.
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use embedded_serial::{BlockingTx, BlockingRxWithTimeout, NonBlockingRx};
+
+use super::cortex_m0::systick;
+use super::uart::{BreakLength, Uart};
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+/// Which checksum a frame uses
+#[derive(PartialEq, Clone, Copy)]
+pub enum Checksum {
+    /// LIN 1.x - data octets only. Also used for the diagnostic frames
+    /// 0x3C and 0x3D in LIN 2.x.
+    Classic,
+    /// LIN 2.x - data octets and the protected identifier
+    Enhanced,
+}
+
+/// The ways a LIN transfer can go wrong
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LinError {
+    /// No break, or not enough octets, arrived in time
+    Timeout,
+    /// An octet we sent did not read back the same
+    BitError,
+    /// The sync field was not 0x55
+    SyncError,
+    /// The protected identifier parity bits were wrong
+    ParityError,
+    /// The received checksum did not match the data
+    ChecksumError,
+    /// The identifier is not in the range 0..63, or more than
+    /// eight data octets were given
+    BadArgument,
+}
+
+// ****************************************************************************
+//
+// Public Data
+//
+// ****************************************************************************
+
+/// The highest frame identifier
+pub const MAX_ID: u8 = 0x3F;
+
+/// The most data octets a frame can carry
+pub const MAX_DATA_LEN: usize = 8;
+
+/// The value of the sync field that follows the break
+pub const SYNC: u8 = 0x55;
+
+// ****************************************************************************
+//
+// Private Types
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+/// Calculate the protected identifier for a frame identifier, by adding
+/// the two parity bits P0 and P1 in bits 6 and 7.
+pub fn protected_id(id: u8) -> u8 {
+    let id = id & MAX_ID;
+    let bit = |n: u8| (id >> n) & 1;
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+    id | (p0 << 6) | (p1 << 7)
+}
+
+/// Check the parity bits on a received protected identifier and return
+/// the frame identifier.
+pub fn check_protected_id(pid: u8) -> Result<u8, LinError> {
+    let id = pid & MAX_ID;
+    if protected_id(id) == pid {
+        Ok(id)
+    } else {
+        Err(LinError::ParityError)
+    }
+}
+
+/// Calculate the checksum octet for a response. The sum is an eight bit
+/// sum with carry, inverted.
+pub fn checksum(pid: u8, data: &[u8], kind: Checksum) -> u8 {
+    let mut sum: u16 = match kind {
+        Checksum::Classic => 0,
+        Checksum::Enhanced => pid as u16,
+    };
+    for &b in data {
+        sum += b as u16;
+        if sum > 0xFF {
+            sum -= 0xFF;
+        }
+    }
+    !(sum as u8)
+}
+
+/// Configure the UART for LIN. Enables break detection (`send_header`
+/// always sends a 13-bit break). If `interrupt` is true, the UART
+/// interrupt will fire on every break - useful for slaves.
+///
+/// Break detection stops the UART receiving data, so it's disarmed when
+/// a break is seen, and re-armed by the functions here once the frame's
+/// response is over (or has failed).
+pub fn init(uart: &mut Uart, interrupt: bool) {
+    uart.enable_lin_break_detect(interrupt);
+}
+
+/// Master: send a frame header for frame `id`. The break and each octet
+/// are read back from the bus to check for collisions. `timeout` is in
+/// SysTick ticks, and applies to each read back.
+pub fn send_header(uart: &mut Uart, id: u8, timeout: usize) -> Result<(), LinError> {
+    if id > MAX_ID {
+        return Err(LinError::BadArgument);
+    }
+    // Drop anything stale sitting in the receiver
+    while uart.getc_try().is_ok() {}
+    uart.rearm_lin_break_detect();
+    uart.send_break(BreakLength::Long);
+    let start = systick::run_time_ticks();
+    while !uart.lin_break_detected() {
+        if (systick::run_time_ticks() - start) >= (timeout as u64) {
+            return Err(LinError::Timeout);
+        }
+    }
+    // Break detection is now disarmed, so we can read back the header
+    let result = send_checked(uart, SYNC, timeout)
+        .and_then(|_| send_checked(uart, protected_id(id), timeout));
+    if result.is_err() {
+        uart.rearm_lin_break_detect();
+    }
+    result
+}
+
+/// Master: publish a frame. Sends the header for `id`, followed by `data`
+/// and its checksum.
+pub fn master_write(uart: &mut Uart,
+                    id: u8,
+                    data: &[u8],
+                    kind: Checksum,
+                    timeout: usize)
+                    -> Result<(), LinError> {
+    if data.len() > MAX_DATA_LEN {
+        return Err(LinError::BadArgument);
+    }
+    send_header(uart, id, timeout)?;
+    send_response(uart, protected_id(id), data, kind, timeout)
+}
+
+/// Master: request a frame from a slave. Sends the header for `id`, then
+/// receives `data.len()` octets and checks the checksum.
+pub fn master_read(uart: &mut Uart,
+                   id: u8,
+                   data: &mut [u8],
+                   kind: Checksum,
+                   timeout: usize)
+                   -> Result<(), LinError> {
+    if data.len() > MAX_DATA_LEN {
+        return Err(LinError::BadArgument);
+    }
+    send_header(uart, id, timeout)?;
+    receive_response(uart, protected_id(id), data, kind, timeout)
+}
+
+/// Slave: wait for a frame header and return the frame identifier. Call
+/// this after `Uart::lin_break_detected` has returned true (either from
+/// polling, or in the UART interrupt handler). Follow it with
+/// `send_response`, `receive_response` or `ignore_response`.
+pub fn receive_header(uart: &mut Uart, timeout: usize) -> Result<u8, LinError> {
+    let result = receive_header_octets(uart, timeout);
+    if result.is_err() {
+        uart.rearm_lin_break_detect();
+    }
+    result
+}
+
+/// Slave: skip the response to a header we don't take part in, and go
+/// back to waiting for the next break.
+pub fn ignore_response(uart: &mut Uart) {
+    uart.rearm_lin_break_detect();
+}
+
+/// Send a response (data octets and checksum) for the frame with
+/// protected identifier `pid`. Used by slaves answering a header, and
+/// by the master when publishing. Break detection is re-armed
+/// afterwards.
+pub fn send_response(uart: &mut Uart,
+                     pid: u8,
+                     data: &[u8],
+                     kind: Checksum,
+                     timeout: usize)
+                     -> Result<(), LinError> {
+    let result = send_response_octets(uart, pid, data, kind, timeout);
+    uart.rearm_lin_break_detect();
+    result
+}
+
+/// Receive a response (data octets and checksum) for the frame with
+/// protected identifier `pid`, filling `data`. Break detection is
+/// re-armed afterwards.
+pub fn receive_response(uart: &mut Uart,
+                        pid: u8,
+                        data: &mut [u8],
+                        kind: Checksum,
+                        timeout: usize)
+                        -> Result<(), LinError> {
+    let result = receive_response_octets(uart, pid, data, kind, timeout);
+    uart.rearm_lin_break_detect();
+    result
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+/// Read the sync octet and protected identifier of a header.
+fn receive_header_octets(uart: &mut Uart, timeout: usize) -> Result<u8, LinError> {
+    let sync = uart.getc(timeout).map_err(|_| LinError::Timeout)?;
+    if sync != SYNC {
+        return Err(LinError::SyncError);
+    }
+    let pid = uart.getc(timeout).map_err(|_| LinError::Timeout)?;
+    check_protected_id(pid)
+}
+
+/// The body of `send_response`.
+fn send_response_octets(uart: &mut Uart,
+                        pid: u8,
+                        data: &[u8],
+                        kind: Checksum,
+                        timeout: usize)
+                        -> Result<(), LinError> {
+    if data.len() > MAX_DATA_LEN {
+        return Err(LinError::BadArgument);
+    }
+    for &b in data {
+        send_checked(uart, b, timeout)?;
+    }
+    send_checked(uart, checksum(pid, data, kind), timeout)
+}
+
+/// The body of `receive_response`.
+fn receive_response_octets(uart: &mut Uart,
+                           pid: u8,
+                           data: &mut [u8],
+                           kind: Checksum,
+                           timeout: usize)
+                           -> Result<(), LinError> {
+    if data.len() > MAX_DATA_LEN {
+        return Err(LinError::BadArgument);
+    }
+    for b in data.iter_mut() {
+        *b = uart.getc(timeout).map_err(|_| LinError::Timeout)?;
+    }
+    let sum = uart.getc(timeout).map_err(|_| LinError::Timeout)?;
+    if sum == checksum(pid, data, kind) {
+        Ok(())
+    } else {
+        Err(LinError::ChecksumError)
+    }
+}
+
+/// Send an octet and check the transceiver echoes it back unchanged.
+fn send_checked(uart: &mut Uart, value: u8, timeout: usize) -> Result<(), LinError> {
+    uart.putc(value).unwrap();
+    match uart.getc(timeout) {
+        Ok(x) if x == value => Ok(()),
+        Ok(_) => Err(LinError::BitError),
+        Err(_) => Err(LinError::Timeout),
+    }
+}
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
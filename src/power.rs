@@ -0,0 +1,106 @@
+//! # Low-power modes for the KE06Z
+//!
+//! The KE06Z supports Run, Wait and Stop modes. In Wait mode the core
+//! clock stops but the peripherals keep running. In Stop mode the
+//! oscillator and FLL also stop, and only asynchronous events (like a
+//! UART RxD edge, KBI or IRQ pin) can wake the chip.
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use cortex_m::asm::wfi;
+use cortex_m::peripheral as cm_periph;
+
+use super::ics;
+use super::registers as reg;
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// Public Data
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// Private Types
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+/// System Control Register - use deep sleep (i.e. Stop mode) on WFI
+const SCB_SCR_SLEEPDEEP: u32 = 1 << 2;
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+/// Enter Wait mode until the next interrupt.
+pub fn wait() {
+    unsafe {
+        cm_periph::scb_mut().scr.modify(|x| x & !SCB_SCR_SLEEPDEEP);
+        wfi();
+    }
+}
+
+/// Enter Stop mode until the next interrupt.
+///
+/// When we wake, we wait for the oscillator and FLL to lock again before
+/// returning, so the bus clock (and hence any UART baud rate) is correct
+/// by the time the caller runs. Interrupt handlers run before that
+/// happens, so they should do as little as possible.
+pub fn stop() {
+    unsafe {
+        cm_periph::scb_mut().scr.modify(|x| x | SCB_SCR_SLEEPDEEP);
+        wfi();
+        cm_periph::scb_mut().scr.modify(|x| x & !SCB_SCR_SLEEPDEEP);
+    }
+    ics::wait_for_lock();
+}
+
+/// Turn on the bandgap buffer, so the bandgap reference can be measured
+/// by the ADC or used by an analog comparator's DAC.
+///
+/// The low-voltage detect enables in SPMSC1 (LVDE and LVDRE) can only be
+/// written once after reset, and this write counts. They are written back
+/// as they are, so whatever the low-voltage detect settings are now (the
+/// reset defaults, unless they've been changed), they are locked until the
+/// next reset. Set them up first if they matter.
+pub fn enable_bandgap_buffer() {
+    reg::get_pmc().spmsc1.modify(|x| x | reg::PMC_SPMSC1_BGBE);
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
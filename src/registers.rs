@@ -35,6 +35,159 @@ pub struct UartRegisters {
     pub data: RW<u8>,
 }
 
+/// SPI Module
+#[repr(C, packed)]
+pub struct SpiRegisters {
+    /// Control register 1
+    pub c1: RW<u8>,
+    /// Control register 2
+    pub c2: RW<u8>,
+    /// Baud rate register
+    pub br: RW<u8>,
+    /// Status register
+    pub s: RW<u8>,
+    _reserved0: u8,
+    /// Data register
+    pub d: RW<u8>,
+    _reserved1: u8,
+    /// Match register
+    pub m: RW<u8>,
+}
+
+/// I2C Module
+#[repr(C, packed)]
+pub struct I2cRegisters {
+    /// Address register 1
+    pub a1: RW<u8>,
+    /// Frequency divider register
+    pub f: RW<u8>,
+    /// Control register 1
+    pub c1: RW<u8>,
+    /// Status register
+    pub s: RW<u8>,
+    /// Data register
+    pub d: RW<u8>,
+    /// Control register 2
+    pub c2: RW<u8>,
+    /// Programmable input glitch filter register
+    pub flt: RW<u8>,
+    /// Range address register
+    pub ra: RW<u8>,
+    /// SMBus control and status register
+    pub smb: RW<u8>,
+    /// Address register 2
+    pub a2: RW<u8>,
+    /// SCL low timeout register high
+    pub slth: RW<u8>,
+    /// SCL low timeout register low
+    pub sltl: RW<u8>,
+}
+
+/// Analog-to-Digital Converter
+#[repr(C, packed)]
+pub struct AdcRegisters {
+    /// Status and control register 1
+    pub sc1: RW<u32>,
+    /// Status and control register 2
+    pub sc2: RW<u32>,
+    /// Status and control register 3
+    pub sc3: RW<u32>,
+    /// Status and control register 4
+    pub sc4: RW<u32>,
+    /// Conversion result register
+    pub r: RO<u32>,
+    /// Compare value register
+    pub cv: RW<u32>,
+    /// Pin control 1 register
+    pub apctl1: RW<u32>,
+    /// Status and control register 5
+    pub sc5: RW<u32>,
+}
+
+/// One FlexTimer channel
+#[repr(C, packed)]
+pub struct FtmChannelRegisters {
+    /// Channel status and control
+    pub sc: RW<u32>,
+    /// Channel value
+    pub v: RW<u32>,
+}
+
+/// FlexTimer Module. FTM0 and FTM1 only implement the registers up to
+/// and including `cntin`, and only two channels. FTM2 has six channels.
+#[repr(C, packed)]
+pub struct FtmRegisters {
+    /// Status and control
+    pub sc: RW<u32>,
+    /// Counter
+    pub cnt: RW<u32>,
+    /// Modulo
+    pub modulo: RW<u32>,
+    /// Channels 0 to 7
+    pub channels: [FtmChannelRegisters; 8],
+    /// Counter initial value
+    pub cntin: RW<u32>,
+    /// Capture and compare status
+    pub status: RW<u32>,
+    /// Features mode selection
+    pub mode: RW<u32>,
+    /// Synchronization
+    pub sync: RW<u32>,
+    /// Initial state for channels output
+    pub outinit: RW<u32>,
+    /// Output mask
+    pub outmask: RW<u32>,
+    /// Function for linked channels
+    pub combine: RW<u32>,
+    /// Deadtime insertion control
+    pub deadtime: RW<u32>,
+    /// FTM external trigger
+    pub exttrig: RW<u32>,
+    /// Channels polarity
+    pub pol: RW<u32>,
+    /// Fault mode status
+    pub fms: RW<u32>,
+    /// Input capture filter control
+    pub filter: RW<u32>,
+    /// Fault control
+    pub fltctrl: RW<u32>,
+    _reserved0: u32,
+    /// Configuration
+    pub conf: RW<u32>,
+    /// FTM fault input polarity
+    pub fltpol: RW<u32>,
+    /// Synchronization configuration
+    pub synconf: RW<u32>,
+    /// FTM inverting control
+    pub invctrl: RW<u32>,
+    /// FTM software output control
+    pub swoctrl: RW<u32>,
+    /// FTM PWM load
+    pub pwmload: RW<u32>,
+}
+
+/// Power Management Controller
+#[repr(C, packed)]
+pub struct PmcRegisters {
+    /// System Power Management Status and Control 1 Register
+    pub spmsc1: RW<u8>,
+    /// System Power Management Status and Control 2 Register
+    pub spmsc2: RW<u8>,
+}
+
+/// Analog Comparator
+#[repr(C, packed)]
+pub struct AcmpRegisters {
+    /// Control and status register
+    pub cs: RW<u8>,
+    /// Control register 0
+    pub c0: RW<u8>,
+    /// Control register 1
+    pub c1: RW<u8>,
+    /// Control register 2
+    pub c2: RW<u8>,
+}
+
 /// GPIO Module.
 ///
 /// The GPIO is represented as three 32-bit banks.
@@ -121,13 +274,24 @@ pub struct OscRegisters {
 //
 // ****************************************************************************
 
+pub const FTM0_BASE: usize = 0x40038000;
+pub const FTM1_BASE: usize = 0x40039000;
+pub const FTM2_BASE: usize = 0x4003A000;
+pub const ADC_BASE: usize = 0x4003B000;
 pub const SIM_BASE: usize = 0x40048000;
 pub const PORT_BASE: usize = 0x40049000;
 pub const ICS_BASE: usize = 0x40064000;
+pub const I2C0_BASE: usize = 0x40066000;
+pub const I2C1_BASE: usize = 0x40067000;
 pub const OSC_BASE: usize = 0x40065000;
 pub const UART0_BASE: usize = 0x4006A000;
 pub const UART1_BASE: usize = 0x4006B000;
 pub const UART2_BASE: usize = 0x4006C000;
+pub const PMC_BASE: usize = 0x4007D000;
+pub const ACMP0_BASE: usize = 0x40073000;
+pub const ACMP1_BASE: usize = 0x40074000;
+pub const SPI0_BASE: usize = 0x40076000;
+pub const SPI1_BASE: usize = 0x40077000;
 pub const GPIO0_BASE: usize = 0xF8000000;
 pub const GPIO1_BASE: usize = 0xF8000040;
 pub const GPIO2_BASE: usize = 0xF8000080;
@@ -159,6 +323,11 @@ pub const ICS_STATUS_IREFST: u8 = 1 << 4;
 pub const ICS_STATUS_LOCK: u8 = 1 << 6;
 pub const ICS_STATUS_LOLS: u8 = 1 << 7;
 
+pub const UART_BDH_SBR: u8 = 0b11111 << 0;
+pub const UART_BDH_SBNS: u8 = 1 << 5;
+pub const UART_BDH_RXEDGIE: u8 = 1 << 6;
+pub const UART_BDH_LBKDIE: u8 = 1 << 7;
+
 pub const UART_C1_PT: u8 = 1 << 0;
 pub const UART_C1_PE: u8 = 1 << 1;
 pub const UART_C1_ILT: u8 = 1 << 2;
@@ -203,6 +372,263 @@ pub const UART_C3_TXDIR: u8 = 1 << 5;
 pub const UART_C3_T8: u8 = 1 << 6;
 pub const UART_C3_R8: u8 = 1 << 7;
 
+pub const SPI_C1_LSBFE: u8 = 1 << 0;
+pub const SPI_C1_SSOE: u8 = 1 << 1;
+pub const SPI_C1_CPHA: u8 = 1 << 2;
+pub const SPI_C1_CPOL: u8 = 1 << 3;
+pub const SPI_C1_MSTR: u8 = 1 << 4;
+pub const SPI_C1_SPTIE: u8 = 1 << 5;
+pub const SPI_C1_SPE: u8 = 1 << 6;
+pub const SPI_C1_SPIE: u8 = 1 << 7;
+
+pub const SPI_C2_SPC0: u8 = 1 << 0;
+pub const SPI_C2_SPISWAI: u8 = 1 << 1;
+pub const SPI_C2_BIDIROE: u8 = 1 << 3;
+pub const SPI_C2_MODFEN: u8 = 1 << 4;
+pub const SPI_C2_SPMIE: u8 = 1 << 7;
+
+pub const SPI_BR_SPR: u8 = 0b1111 << 0;
+pub fn spi_br_spr(x: u8) -> u8 {
+    (x & 0b1111) << 0
+}
+pub const SPI_BR_SPPR: u8 = 0b111 << 4;
+pub fn spi_br_sppr(x: u8) -> u8 {
+    (x & 0b111) << 4
+}
+
+pub const SPI_S_MODF: u8 = 1 << 4;
+pub const SPI_S_SPTEF: u8 = 1 << 5;
+pub const SPI_S_SPMF: u8 = 1 << 6;
+pub const SPI_S_SPRF: u8 = 1 << 7;
+
+pub const I2C_F_ICR: u8 = 0b111111 << 0;
+pub fn i2c_f_icr(x: u8) -> u8 {
+    (x & 0b111111) << 0
+}
+pub const I2C_F_MULT: u8 = 0b11 << 6;
+pub fn i2c_f_mult(x: u8) -> u8 {
+    (x & 0b11) << 6
+}
+
+pub const I2C_C1_WUEN: u8 = 1 << 1;
+pub const I2C_C1_RSTA: u8 = 1 << 2;
+pub const I2C_C1_TXAK: u8 = 1 << 3;
+pub const I2C_C1_TX: u8 = 1 << 4;
+pub const I2C_C1_MST: u8 = 1 << 5;
+pub const I2C_C1_IICIE: u8 = 1 << 6;
+pub const I2C_C1_IICEN: u8 = 1 << 7;
+
+pub const I2C_S_RXAK: u8 = 1 << 0;
+pub const I2C_S_IICIF: u8 = 1 << 1;
+pub const I2C_S_SRW: u8 = 1 << 2;
+pub const I2C_S_RAM: u8 = 1 << 3;
+pub const I2C_S_ARBL: u8 = 1 << 4;
+pub const I2C_S_BUSY: u8 = 1 << 5;
+pub const I2C_S_IAAS: u8 = 1 << 6;
+pub const I2C_S_TCF: u8 = 1 << 7;
+
+pub const I2C_C2_AD: u8 = 0b111 << 0;
+pub fn i2c_c2_ad(x: u8) -> u8 {
+    (x & 0b111) << 0
+}
+pub const I2C_C2_RMEN: u8 = 1 << 3;
+pub const I2C_C2_SBRC: u8 = 1 << 4;
+pub const I2C_C2_ADEXT: u8 = 1 << 6;
+pub const I2C_C2_GCAEN: u8 = 1 << 7;
+
+pub const I2C_FLT_FLT: u8 = 0b1111 << 0;
+pub const I2C_FLT_STARTF: u8 = 1 << 4;
+pub const I2C_FLT_SSIE: u8 = 1 << 5;
+pub const I2C_FLT_STOPF: u8 = 1 << 6;
+pub const I2C_FLT_SHEN: u8 = 1 << 7;
+
+pub const I2C_SMB_SHTF2IE: u8 = 1 << 0;
+pub const I2C_SMB_SHTF2: u8 = 1 << 1;
+pub const I2C_SMB_SHTF1: u8 = 1 << 2;
+pub const I2C_SMB_SLTF: u8 = 1 << 3;
+pub const I2C_SMB_TCKSEL: u8 = 1 << 4;
+pub const I2C_SMB_SIICAEN: u8 = 1 << 5;
+pub const I2C_SMB_ALERTEN: u8 = 1 << 6;
+pub const I2C_SMB_FACK: u8 = 1 << 7;
+
+pub const ADC_SC1_ADCH: u32 = 0b11111 << 0;
+pub fn adc_sc1_adch(x: u32) -> u32 {
+    (x & 0b11111) << 0
+}
+pub const ADC_SC1_ADCO: u32 = 1 << 5;
+pub const ADC_SC1_AIEN: u32 = 1 << 6;
+pub const ADC_SC1_COCO: u32 = 1 << 7;
+
+pub const ADC_SC2_REFSEL: u32 = 0b11 << 0;
+pub const ADC_SC2_FFULL: u32 = 1 << 2;
+pub const ADC_SC2_FEMPTY: u32 = 1 << 3;
+pub const ADC_SC2_ACFGT: u32 = 1 << 4;
+pub const ADC_SC2_ACFE: u32 = 1 << 5;
+pub const ADC_SC2_ADTRG: u32 = 1 << 6;
+pub const ADC_SC2_ADACT: u32 = 1 << 7;
+
+pub const ADC_SC3_ADICLK: u32 = 0b11 << 0;
+pub fn adc_sc3_adiclk(x: u32) -> u32 {
+    (x & 0b11) << 0
+}
+pub const ADC_SC3_MODE: u32 = 0b11 << 2;
+pub fn adc_sc3_mode(x: u32) -> u32 {
+    (x & 0b11) << 2
+}
+pub const ADC_SC3_ADLSMP: u32 = 1 << 4;
+pub const ADC_SC3_ADIV: u32 = 0b11 << 5;
+pub fn adc_sc3_adiv(x: u32) -> u32 {
+    (x & 0b11) << 5
+}
+pub const ADC_SC3_ADLPC: u32 = 1 << 7;
+
+pub const ADC_SC4_AFDEP: u32 = 0b111 << 0;
+pub fn adc_sc4_afdep(x: u32) -> u32 {
+    (x & 0b111) << 0
+}
+pub const ADC_SC4_ACFSEL: u32 = 1 << 5;
+pub const ADC_SC4_ASCANE: u32 = 1 << 6;
+pub const ADC_SC4_HTRGME: u32 = 1 << 8;
+
+pub const ADC_R_ADR: u32 = 0xFFF;
+
+pub const ADC_SC5_HTRGMASKSEL: u32 = 1 << 0;
+pub const ADC_SC5_HTRGMASKE: u32 = 1 << 1;
+
+pub const FTM_SC_PS: u32 = 0b111 << 0;
+pub fn ftm_sc_ps(x: u32) -> u32 {
+    (x & 0b111) << 0
+}
+pub const FTM_SC_CLKS: u32 = 0b11 << 3;
+pub fn ftm_sc_clks(x: u32) -> u32 {
+    (x & 0b11) << 3
+}
+pub const FTM_SC_CPWMS: u32 = 1 << 5;
+pub const FTM_SC_TOIE: u32 = 1 << 6;
+pub const FTM_SC_TOF: u32 = 1 << 7;
+
+pub const FTM_CNSC_ELSA: u32 = 1 << 2;
+pub const FTM_CNSC_ELSB: u32 = 1 << 3;
+pub const FTM_CNSC_MSA: u32 = 1 << 4;
+pub const FTM_CNSC_MSB: u32 = 1 << 5;
+pub const FTM_CNSC_CHIE: u32 = 1 << 6;
+pub const FTM_CNSC_CHF: u32 = 1 << 7;
+
+pub const FTM_MODE_FTMEN: u32 = 1 << 0;
+pub const FTM_MODE_INIT: u32 = 1 << 1;
+pub const FTM_MODE_WPDIS: u32 = 1 << 2;
+pub const FTM_MODE_PWMSYNC: u32 = 1 << 3;
+pub const FTM_MODE_FAULTM: u32 = 0b11 << 5;
+pub fn ftm_mode_faultm(x: u32) -> u32 {
+    (x & 0b11) << 5
+}
+pub const FTM_MODE_FAULTIE: u32 = 1 << 7;
+
+pub const FTM_SYNC_CNTMIN: u32 = 1 << 0;
+pub const FTM_SYNC_CNTMAX: u32 = 1 << 1;
+pub const FTM_SYNC_REINIT: u32 = 1 << 2;
+pub const FTM_SYNC_SYNCHOM: u32 = 1 << 3;
+pub const FTM_SYNC_TRIG0: u32 = 1 << 4;
+pub const FTM_SYNC_TRIG1: u32 = 1 << 5;
+pub const FTM_SYNC_TRIG2: u32 = 1 << 6;
+pub const FTM_SYNC_SWSYNC: u32 = 1 << 7;
+
+pub const FTM_COMBINE_SYNCEN0: u32 = 1 << 5;
+pub const FTM_COMBINE_SYNCEN1: u32 = 1 << 13;
+pub const FTM_COMBINE_SYNCEN2: u32 = 1 << 21;
+pub const FTM_COMBINE_FAULTEN0: u32 = 1 << 6;
+pub const FTM_COMBINE_FAULTEN1: u32 = 1 << 14;
+pub const FTM_COMBINE_FAULTEN2: u32 = 1 << 22;
+
+pub const FTM_EXTTRIG_CH2TRIG: u32 = 1 << 0;
+pub const FTM_EXTTRIG_CH3TRIG: u32 = 1 << 1;
+pub const FTM_EXTTRIG_CH4TRIG: u32 = 1 << 2;
+pub const FTM_EXTTRIG_CH5TRIG: u32 = 1 << 3;
+pub const FTM_EXTTRIG_CH0TRIG: u32 = 1 << 4;
+pub const FTM_EXTTRIG_CH1TRIG: u32 = 1 << 5;
+pub const FTM_EXTTRIG_INITTRIGEN: u32 = 1 << 6;
+pub const FTM_EXTTRIG_TRIGF: u32 = 1 << 7;
+
+pub const FTM_FMS_FAULTF0: u32 = 1 << 0;
+pub const FTM_FMS_FAULTF1: u32 = 1 << 1;
+pub const FTM_FMS_FAULTF2: u32 = 1 << 2;
+pub const FTM_FMS_FAULTF3: u32 = 1 << 3;
+pub const FTM_FMS_FAULTIN: u32 = 1 << 5;
+pub const FTM_FMS_WPEN: u32 = 1 << 6;
+pub const FTM_FMS_FAULTF: u32 = 1 << 7;
+
+pub const FTM_FLTCTRL_FAULT0EN: u32 = 1 << 0;
+pub const FTM_FLTCTRL_FAULT1EN: u32 = 1 << 1;
+pub const FTM_FLTCTRL_FAULT2EN: u32 = 1 << 2;
+pub const FTM_FLTCTRL_FAULT3EN: u32 = 1 << 3;
+pub const FTM_FLTCTRL_FFLTR0EN: u32 = 1 << 4;
+pub const FTM_FLTCTRL_FFVAL: u32 = 0b1111 << 8;
+pub fn ftm_fltctrl_ffval(x: u32) -> u32 {
+    (x & 0b1111) << 8
+}
+
+pub const FTM_FLTPOL_FLT0POL: u32 = 1 << 0;
+
+pub const FTM_SYNCONF_HWTRIGMODE: u32 = 1 << 0;
+pub const FTM_SYNCONF_CNTINC: u32 = 1 << 2;
+pub const FTM_SYNCONF_SYNCMODE: u32 = 1 << 7;
+pub const FTM_SYNCONF_SWRSTCNT: u32 = 1 << 8;
+pub const FTM_SYNCONF_SWWRBUF: u32 = 1 << 9;
+pub const FTM_SYNCONF_HWRSTCNT: u32 = 1 << 16;
+pub const FTM_SYNCONF_HWWRBUF: u32 = 1 << 17;
+
+pub const PMC_SPMSC1_BGBE: u8 = 1 << 0;
+pub const PMC_SPMSC1_LVDE: u8 = 1 << 2;
+pub const PMC_SPMSC1_LVDSE: u8 = 1 << 3;
+pub const PMC_SPMSC1_LVDRE: u8 = 1 << 4;
+pub const PMC_SPMSC1_LVWIE: u8 = 1 << 5;
+pub const PMC_SPMSC1_LVWACK: u8 = 1 << 6;
+pub const PMC_SPMSC1_LVWF: u8 = 1 << 7;
+
+pub const ACMP_CS_ACMOD: u8 = 0b11 << 0;
+pub fn acmp_cs_acmod(x: u8) -> u8 {
+    (x & 0b11) << 0
+}
+pub const ACMP_CS_ACOPE: u8 = 1 << 2;
+pub const ACMP_CS_ACO: u8 = 1 << 3;
+pub const ACMP_CS_ACIE: u8 = 1 << 4;
+pub const ACMP_CS_ACF: u8 = 1 << 5;
+pub const ACMP_CS_HYST: u8 = 1 << 6;
+pub const ACMP_CS_ACE: u8 = 1 << 7;
+
+pub const ACMP_C0_ACNSEL: u8 = 0b11 << 0;
+pub fn acmp_c0_acnsel(x: u8) -> u8 {
+    (x & 0b11) << 0
+}
+pub const ACMP_C0_ACPSEL: u8 = 0b11 << 4;
+pub fn acmp_c0_acpsel(x: u8) -> u8 {
+    (x & 0b11) << 4
+}
+
+pub const ACMP_C1_DACVAL: u8 = 0b111111 << 0;
+pub fn acmp_c1_dacval(x: u8) -> u8 {
+    (x & 0b111111) << 0
+}
+pub const ACMP_C1_DACREF: u8 = 1 << 6;
+pub const ACMP_C1_DACEN: u8 = 1 << 7;
+
+pub const ACMP_C2_ACIPE: u8 = 0b111 << 0;
+pub fn acmp_c2_acipe(x: u8) -> u8 {
+    (x & 0b111) << 0
+}
+
+pub const SIM_SOPT0_ACTRG: u32 = 1 << 5;
+pub const SIM_SOPT0_ACIC: u32 = 1 << 11;
+pub const SIM_SOPT0_ADHWT: u32 = 0b111 << 20;
+pub fn sim_sopt0_adhwt(x: u32) -> u32 {
+    (x & 0b111) << 20
+}
+pub const SIM_SOPT0_DLYACT: u32 = 1 << 23;
+pub const SIM_SOPT0_DELAY: u32 = 0xFF << 24;
+pub fn sim_sopt0_delay(x: u32) -> u32 {
+    (x & 0xFF) << 24
+}
+
 pub const SIM_SCGC_RTC: u32 = 1 << 0;
 pub const SIM_SCGC_PIT: u32 = 1 << 1;
 pub const SIM_SCGC_PWT: u32 = 1 << 4;
@@ -268,6 +694,50 @@ pub fn get_uart2() -> &'static mut UartRegisters {
     unsafe { &mut *(UART2_BASE as *mut UartRegisters) }
 }
 
+pub fn get_spi0() -> &'static mut SpiRegisters {
+    unsafe { &mut *(SPI0_BASE as *mut SpiRegisters) }
+}
+
+pub fn get_spi1() -> &'static mut SpiRegisters {
+    unsafe { &mut *(SPI1_BASE as *mut SpiRegisters) }
+}
+
+pub fn get_i2c0() -> &'static mut I2cRegisters {
+    unsafe { &mut *(I2C0_BASE as *mut I2cRegisters) }
+}
+
+pub fn get_i2c1() -> &'static mut I2cRegisters {
+    unsafe { &mut *(I2C1_BASE as *mut I2cRegisters) }
+}
+
+pub fn get_ftm0() -> &'static mut FtmRegisters {
+    unsafe { &mut *(FTM0_BASE as *mut FtmRegisters) }
+}
+
+pub fn get_ftm1() -> &'static mut FtmRegisters {
+    unsafe { &mut *(FTM1_BASE as *mut FtmRegisters) }
+}
+
+pub fn get_ftm2() -> &'static mut FtmRegisters {
+    unsafe { &mut *(FTM2_BASE as *mut FtmRegisters) }
+}
+
+pub fn get_adc() -> &'static mut AdcRegisters {
+    unsafe { &mut *(ADC_BASE as *mut AdcRegisters) }
+}
+
+pub fn get_pmc() -> &'static mut PmcRegisters {
+    unsafe { &mut *(PMC_BASE as *mut PmcRegisters) }
+}
+
+pub fn get_acmp0() -> &'static mut AcmpRegisters {
+    unsafe { &mut *(ACMP0_BASE as *mut AcmpRegisters) }
+}
+
+pub fn get_acmp1() -> &'static mut AcmpRegisters {
+    unsafe { &mut *(ACMP1_BASE as *mut AcmpRegisters) }
+}
+
 pub fn get_gpio0() -> &'static mut GpioRegisters {
     unsafe { &mut *(GPIO0_BASE as *mut GpioRegisters) }
 }
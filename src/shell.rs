@@ -0,0 +1,346 @@
+//! # A simple command shell
+//!
+//! Runs a line-editing command prompt over anything that implements
+//! the `embedded_serial` traits (like a `Uart`). Supports backspace,
+//! Ctrl-C to discard the line, and up/down arrow history. Lines are split
+//! on whitespace and the first word is looked up in a static table of
+//! commands. Nothing is heap allocated.
+//!
+//! ```ignore
+//! static COMMANDS: [Command<Uart>; 1] = [
+//!     Command { name: "led", help: "led <on|off>", handler: cmd_led },
+//! ];
+//!
+//! let mut shell = Shell::new(&COMMANDS, "> ", NewlineMode::SwapLFtoCRLF);
+//! shell.start(&mut uart);
+//! loop {
+//!     shell.poll(&mut uart);
+//! }
+//! ```
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use core::str;
+use embedded_serial::{BlockingTx, NonBlockingRx};
+
+use super::uart::NewlineMode;
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+/// A command handler is given the serial port, and the words on the
+/// command line (including the command name itself as `args[0]`).
+pub type Handler<S> = fn(serial: &mut S, args: &[&str]);
+
+/// An entry in the command table
+pub struct Command<S: 'static> {
+    /// What the user types to run this command
+    pub name: &'static str,
+    /// One line of help text, printed by the built-in `help` command
+    pub help: &'static str,
+    /// Called to run the command
+    pub handler: Handler<S>,
+}
+
+/// A command shell. Holds the line being edited and the history.
+pub struct Shell<S: 'static> {
+    commands: &'static [Command<S>],
+    prompt: &'static str,
+    nl_mode: NewlineMode,
+    line: [u8; MAX_LINE_LEN],
+    len: usize,
+    history: [[u8; MAX_LINE_LEN]; HISTORY_LEN],
+    history_lens: [usize; HISTORY_LEN],
+    /// Where the next history line goes
+    history_next: usize,
+    /// How many history lines are in use
+    history_count: usize,
+    /// How far back in the history we're currently looking (0 = not)
+    history_pos: usize,
+    escape: Escape,
+    /// The last end-of-line character was a CR
+    last_cr: bool,
+}
+
+// ****************************************************************************
+//
+// Public Data
+//
+// ****************************************************************************
+
+/// The longest line we can edit
+pub const MAX_LINE_LEN: usize = 80;
+
+/// The most words a command line can be split into
+pub const MAX_ARGS: usize = 8;
+
+/// How many previous lines we remember
+pub const HISTORY_LEN: usize = 4;
+
+// ****************************************************************************
+//
+// Private Types
+//
+// ****************************************************************************
+
+/// Where we are in an ANSI escape sequence
+#[derive(PartialEq, Clone, Copy)]
+enum Escape {
+    None,
+    /// Seen ESC
+    Esc,
+    /// Seen ESC [
+    Csi,
+}
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+const CTRL_C: u8 = 0x03;
+const ESC: u8 = 0x1B;
+const CR: u8 = 0x0D;
+const LF: u8 = 0x0A;
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+impl<S> Shell<S>
+    where S: BlockingTx + NonBlockingRx + 'static
+{
+    /// Create a new shell, using the given command table. Output line
+    /// endings follow `nl_mode`. On input, CR, LF and CRLF are all
+    /// accepted as the end of a line.
+    pub fn new(commands: &'static [Command<S>],
+               prompt: &'static str,
+               nl_mode: NewlineMode)
+               -> Shell<S> {
+        Shell {
+            commands: commands,
+            prompt: prompt,
+            nl_mode: nl_mode,
+            line: [0; MAX_LINE_LEN],
+            len: 0,
+            history: [[0; MAX_LINE_LEN]; HISTORY_LEN],
+            history_lens: [0; HISTORY_LEN],
+            history_next: 0,
+            history_count: 0,
+            history_pos: 0,
+            escape: Escape::None,
+            last_cr: false,
+        }
+    }
+
+    /// Print the first prompt.
+    pub fn start(&mut self, serial: &mut S) {
+        puts(serial, self.prompt);
+    }
+
+    /// Process any characters waiting on the serial port. Returns when
+    /// there are none left, so this can be called from a main loop.
+    pub fn poll(&mut self, serial: &mut S) {
+        while let Ok(ch) = serial.getc_try() {
+            self.handle_char(serial, ch);
+        }
+    }
+
+    /// Process a single received character.
+    pub fn handle_char(&mut self, serial: &mut S, ch: u8) {
+        let last_cr = self.last_cr;
+        self.last_cr = false;
+        match self.escape {
+            Escape::Esc => {
+                self.escape = if ch == b'[' { Escape::Csi } else { Escape::None };
+                return;
+            }
+            Escape::Csi => {
+                // Parameter and intermediate bytes (e.g. the `3` in
+                // `ESC [ 3 ~`) are skipped until the final byte.
+                if ch >= 0x40 && ch <= 0x7E {
+                    self.escape = Escape::None;
+                    match ch {
+                        b'A' => self.history_up(serial),
+                        b'B' => self.history_down(serial),
+                        _ => {}
+                    }
+                }
+                return;
+            }
+            Escape::None => {}
+        }
+        match ch {
+            ESC => self.escape = Escape::Esc,
+            LF if last_cr => {
+                // Second half of a CRLF
+            }
+            CR | LF => {
+                self.last_cr = ch == CR;
+                self.newline(serial);
+                self.execute(serial);
+                puts(serial, self.prompt);
+            }
+            BACKSPACE | DELETE => {
+                if self.len > 0 {
+                    self.len -= 1;
+                    puts(serial, "\x08 \x08");
+                }
+            }
+            CTRL_C => {
+                self.len = 0;
+                self.history_pos = 0;
+                puts(serial, "^C");
+                self.newline(serial);
+                puts(serial, self.prompt);
+            }
+            0x20...0x7E => {
+                if self.len < MAX_LINE_LEN {
+                    self.line[self.len] = ch;
+                    self.len += 1;
+                    let _ = serial.putc(ch);
+                }
+            }
+            _ => {
+                // Ignore other control characters
+            }
+        }
+    }
+
+    /// Print a newline in the configured style.
+    fn newline(&self, serial: &mut S) {
+        match self.nl_mode {
+            NewlineMode::Binary => puts(serial, "\n"),
+            NewlineMode::SwapLFtoCRLF => puts(serial, "\r\n"),
+        }
+    }
+
+    /// Run the line we have, and clear it ready for the next one.
+    fn execute(&mut self, serial: &mut S) {
+        let line = self.line;
+        let len = self.len;
+        self.len = 0;
+        self.history_pos = 0;
+        if len == 0 {
+            return;
+        }
+        self.push_history(&line[..len]);
+
+        // We only ever store printable ASCII, so this can't fail
+        let text = str::from_utf8(&line[..len]).unwrap_or("");
+        let mut args: [&str; MAX_ARGS] = [""; MAX_ARGS];
+        let mut argc = 0;
+        for word in text.split_whitespace() {
+            if argc == MAX_ARGS {
+                puts(serial, "Too many arguments");
+                self.newline(serial);
+                return;
+            }
+            args[argc] = word;
+            argc += 1;
+        }
+        if argc == 0 {
+            return;
+        }
+
+        if args[0] == "help" {
+            for cmd in self.commands {
+                puts(serial, cmd.name);
+                puts(serial, " - ");
+                puts(serial, cmd.help);
+                self.newline(serial);
+            }
+            return;
+        }
+
+        match self.commands.iter().find(|cmd| cmd.name == args[0]) {
+            Some(cmd) => (cmd.handler)(serial, &args[..argc]),
+            None => {
+                puts(serial, "Unknown command: ");
+                puts(serial, args[0]);
+                self.newline(serial);
+            }
+        }
+    }
+
+    /// Remember a line, unless it's the same as the last one.
+    fn push_history(&mut self, line: &[u8]) {
+        if self.history_count > 0 {
+            let last = (self.history_next + HISTORY_LEN - 1) % HISTORY_LEN;
+            if &self.history[last][..self.history_lens[last]] == line {
+                return;
+            }
+        }
+        self.history[self.history_next][..line.len()].copy_from_slice(line);
+        self.history_lens[self.history_next] = line.len();
+        self.history_next = (self.history_next + 1) % HISTORY_LEN;
+        if self.history_count < HISTORY_LEN {
+            self.history_count += 1;
+        }
+    }
+
+    /// Recall an older line.
+    fn history_up(&mut self, serial: &mut S) {
+        if self.history_pos < self.history_count {
+            self.history_pos += 1;
+            self.recall(serial);
+        }
+    }
+
+    /// Recall a newer line, or go back to an empty one.
+    fn history_down(&mut self, serial: &mut S) {
+        if self.history_pos > 0 {
+            self.history_pos -= 1;
+            self.recall(serial);
+        }
+    }
+
+    /// Replace the line being edited with the one at `history_pos`.
+    fn recall(&mut self, serial: &mut S) {
+        while self.len > 0 {
+            self.len -= 1;
+            puts(serial, "\x08 \x08");
+        }
+        if self.history_pos == 0 {
+            return;
+        }
+        let idx = (self.history_next + HISTORY_LEN - self.history_pos) % HISTORY_LEN;
+        let len = self.history_lens[idx];
+        self.line[..len].copy_from_slice(&self.history[idx][..len]);
+        self.len = len;
+        for &ch in &self.line[..len] {
+            let _ = serial.putc(ch);
+        }
+    }
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+/// Send a string. There's nothing useful we can do about errors.
+fn puts<S: BlockingTx>(serial: &mut S, s: &str) {
+    for b in s.bytes() {
+        let _ = serial.putc(b);
+    }
+}
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
@@ -36,9 +36,9 @@ pub enum ClockGatingPeripheral {
     Swd,
     Flash,
     Crc,
-    Fmt2,
-    Fmt1,
-    Fmt0,
+    Ftm2,
+    Ftm1,
+    Ftm0,
     Pwt,
     Pit,
     Rtc,
@@ -109,9 +109,9 @@ pub fn power_control(peripheral: ClockGatingPeripheral, enable: bool) {
         // 11 is reserved
         ClockGatingPeripheral::Crc => (1 << 10),
         // 9 and 8 are reserved
-        ClockGatingPeripheral::Fmt2 => (1 << 7),
-        ClockGatingPeripheral::Fmt1 => (1 << 6),
-        ClockGatingPeripheral::Fmt0 => (1 << 5),
+        ClockGatingPeripheral::Ftm2 => (1 << 7),
+        ClockGatingPeripheral::Ftm1 => (1 << 6),
+        ClockGatingPeripheral::Ftm0 => (1 << 5),
         ClockGatingPeripheral::Pwt => (1 << 4),
         // 3 and 2 are reserved
         ClockGatingPeripheral::Pit => (1 << 2),
@@ -0,0 +1,576 @@
+//! # SPI for the KE06Z
+//!
+//! The KE06Z has two SPI modules. They have 8-bit data registers, so
+//! 16-bit frames are sent as two back-to-back octets.
+//!
+//! Each module can be a master, or an interrupt-driven slave. In slave
+//! mode, the SPI module has no way to tell us when the master releases
+//! SS, so the application must watch the SS pin (e.g. with a KBI or IRQ
+//! pin interrupt on the rising edge) and call `slave_deselected`.
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use cortex_m::asm::nop;
+
+use super::registers as reg;
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+/// This chip has 2 SPI modules
+#[derive(PartialEq, Clone, Copy)]
+#[allow(missing_docs)]
+pub enum SpiId {
+    Spi0,
+    Spi1,
+}
+
+/// The four combinations of clock polarity and phase
+#[derive(PartialEq, Clone, Copy)]
+pub enum Mode {
+    /// Clock idles low, data sampled on the rising edge
+    Mode0,
+    /// Clock idles low, data sampled on the falling edge
+    Mode1,
+    /// Clock idles high, data sampled on the falling edge
+    Mode2,
+    /// Clock idles high, data sampled on the rising edge
+    Mode3,
+}
+
+/// Which bit goes on the wire first
+#[derive(PartialEq, Clone, Copy)]
+pub enum BitOrder {
+    /// Most significant bit first (the usual)
+    MsbFirst,
+    /// Least significant bit first
+    LsbFirst,
+}
+
+/// The width of a frame on the wire. The 8-bit functions only work with
+/// 8-bit frames, and the 16-bit functions with 16-bit frames.
+#[derive(PartialEq, Clone, Copy)]
+pub enum FrameSize {
+    /// 8-bit frames, for `exchange`, `transfer` and `write`
+    Eight,
+    /// 16-bit frames, for `exchange16`, `transfer16` and `write16`
+    Sixteen,
+}
+
+/// The ways a transfer can fail
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SpiError {
+    /// Another master drove our SS pin low, so the hardware dropped us
+    /// to slave mode. Call `Spi::resume_master` once the bus is free.
+    ModeFault,
+    /// The transfer doesn't match the frame size
+    WrongFrameSize,
+}
+
+/// Called from interrupt context when the match register matches.
+pub type MatchCallback = fn(id: SpiId);
+
+/// Called from interrupt context when a slave transaction completes. Is
+/// given the octets received (up to the size of the receive buffer) and
+/// the total number of octets clocked in that transaction.
+pub type SlaveCallback = fn(id: SpiId, rx: &[u8], count: usize);
+
+/// Controls a single SPI module as a master or a slave.
+///
+/// As a master, chip select is not driven by the hardware - use a GPIO.
+pub struct Spi {
+    id: SpiId,
+    /// Created with `new` rather than `new_slave`
+    master: bool,
+    bit_order: BitOrder,
+    frame_size: FrameSize,
+    reg: &'static mut reg::SpiRegisters,
+}
+
+// ****************************************************************************
+//
+// Private Types
+//
+// ****************************************************************************
+
+/// The buffers used by a slave, shared with the interrupt handler
+struct SlaveState {
+    rx: &'static mut [u8],
+    tx: &'static [u8],
+    count: usize,
+    callback: SlaveCallback,
+}
+
+// ****************************************************************************
+//
+// Public Data
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+/// The Freedom board has an 8 MHz external oscillator
+/// We set the bus clock to 20 MHz
+const CLOCK_SPEED: u32 = 20_000_000;
+
+/// What a slave sends once it runs out of transmit data
+const SLAVE_FILL: u8 = 0xFF;
+
+/// Slave buffers for SPI0 and SPI1. Only touched by the interrupt
+/// handler while the SPI interrupt is enabled.
+static mut SLAVE_STATE: [Option<SlaveState>; 2] = [None, None];
+
+/// Match callbacks for SPI0 and SPI1.
+static mut MATCH_CALLBACK: [Option<MatchCallback>; 2] = [None, None];
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+/// Represents a single SPI module on the KE06Z
+impl Spi {
+    /// Create a new Spi object in master mode. The caller is responsible
+    /// for ensuring that only one object exists per SpiId. The clock is set
+    /// to the fastest rate that does not exceed `baud`.
+    pub fn new(id: SpiId,
+               mode: Mode,
+               bit_order: BitOrder,
+               frame_size: FrameSize,
+               baud: u32)
+               -> Spi {
+        let mut spi = Spi {
+            id: id,
+            master: true,
+            bit_order: bit_order,
+            frame_size: frame_size,
+            reg: get_spi_registers(id),
+        };
+
+        spi.power_up();
+        spi.set_baud(baud);
+        spi.reg.c1.write(reg::SPI_C1_MSTR);
+        spi.set_mode(mode);
+        spi.set_bit_order(bit_order);
+
+        // Turn it back on
+        spi.reg.c1.modify(|x| x | reg::SPI_C1_SPE);
+
+        spi
+    }
+
+    /// Create a new Spi object in slave mode, with 8-bit frames. The
+    /// hardware SS pin is used. The caller is responsible for ensuring
+    /// that only one object exists per SpiId. Call `start_slave` to supply
+    /// buffers, and make sure this module's interrupt handler
+    /// (`spi0_isr` or `spi1_isr`) is in the vector table and enabled.
+    pub fn new_slave(id: SpiId, mode: Mode, bit_order: BitOrder) -> Spi {
+        let mut spi = Spi {
+            id: id,
+            master: false,
+            bit_order: bit_order,
+            frame_size: FrameSize::Eight,
+            reg: get_spi_registers(id),
+        };
+
+        spi.power_up();
+        spi.set_mode(mode);
+        spi.set_bit_order(bit_order);
+
+        // Turn it back on
+        spi.reg.c1.modify(|x| x | reg::SPI_C1_SPE);
+
+        spi
+    }
+
+    /// Supply the buffers for slave transactions. Received octets are
+    /// stored in `rx` (any extra are dropped) and octets from `tx` are
+    /// sent (then `0xFF` once it runs out). The same buffers are used for
+    /// every transaction until this is called again. `callback` is
+    /// called from `slave_deselected` when each transaction ends.
+    pub fn start_slave(&mut self,
+                       rx: &'static mut [u8],
+                       tx: &'static [u8],
+                       callback: SlaveCallback) {
+        // Keep the interrupt handler out while we change things
+        self.reg.c1.modify(|x| x & !reg::SPI_C1_SPIE);
+        unsafe {
+            SLAVE_STATE[self.id as usize] = Some(SlaveState {
+                rx: rx,
+                tx: tx,
+                count: 0,
+                callback: callback,
+            });
+        }
+        // Discard anything stale and queue the first octet
+        if (self.reg.s.read() & reg::SPI_S_SPRF) != 0 {
+            let _ = self.reg.d.read();
+        }
+        self.reg.d.write(tx.first().cloned().unwrap_or(SLAVE_FILL));
+        self.reg.c1.modify(|x| x | reg::SPI_C1_SPIE);
+    }
+
+    /// Stop servicing slave transactions.
+    pub fn stop_slave(&mut self) {
+        self.reg.c1.modify(|x| x & !reg::SPI_C1_SPIE);
+        unsafe {
+            SLAVE_STATE[self.id as usize] = None;
+        }
+    }
+
+    /// Watch received data for `value`, using the hardware match
+    /// register. If `callback` is given, the SPI interrupt is enabled
+    /// and the callback is called from the interrupt handler on a match -
+    /// so the CPU can sleep until a particular command arrives. Otherwise,
+    /// poll with `match_detected`.
+    pub fn enable_match(&mut self, value: u8, callback: Option<MatchCallback>) {
+        self.reg.c2.modify(|x| x & !reg::SPI_C2_SPMIE);
+        self.clear_match();
+        self.reg.m.write(value);
+        unsafe {
+            MATCH_CALLBACK[self.id as usize] = callback;
+        }
+        if callback.is_some() {
+            self.reg.c2.modify(|x| x | reg::SPI_C2_SPMIE);
+        }
+    }
+
+    /// Stop the match interrupt.
+    pub fn disable_match(&mut self) {
+        self.reg.c2.modify(|x| x & !reg::SPI_C2_SPMIE);
+        unsafe {
+            MATCH_CALLBACK[self.id as usize] = None;
+        }
+    }
+
+    /// Returns true if the match register has matched since the last
+    /// call, clearing the flag.
+    pub fn match_detected(&mut self) -> bool {
+        if (self.reg.s.read() & reg::SPI_S_SPMF) != 0 {
+            self.clear_match();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Enable or disable mode fault detection (master mode only). When
+    /// enabled, the SS pin is an input, and if another master drives it
+    /// low, this module immediately stops driving the bus and transfers
+    /// fail with `SpiError::ModeFault`. Use a GPIO for chip select.
+    pub fn enable_mode_fault(&mut self, enable: bool) {
+        self.reg.c1.modify(|x| x & !reg::SPI_C1_SSOE);
+        if enable {
+            self.reg.c2.modify(|x| x | reg::SPI_C2_MODFEN);
+        } else {
+            self.reg.c2.modify(|x| x & !reg::SPI_C2_MODFEN);
+        }
+    }
+
+    /// Returns true if a mode fault has occurred. The flag stays set
+    /// until `resume_master` is called. Always false for a slave.
+    pub fn mode_fault(&self) -> bool {
+        self.master &&
+        ((self.reg.s.read() & reg::SPI_S_MODF) != 0 ||
+         (self.reg.c1.read() & reg::SPI_C1_MSTR) == 0)
+    }
+
+    /// Take back control of the bus after a mode fault. Clears the fault
+    /// flag and returns to master mode.
+    pub fn resume_master(&mut self) {
+        // Reading S with MODF set and then writing C1 clears MODF
+        let _ = self.reg.s.read();
+        self.reg.c1.modify(|x| x | reg::SPI_C1_MSTR);
+    }
+
+    /// Set the clock to the fastest rate that does not exceed `baud`.
+    /// Returns the rate actually chosen.
+    pub fn set_baud(&mut self, baud: u32) -> u32 {
+        let (sppr, spr, divisor) = calculate_divisor(baud);
+        self.reg.br.write(reg::spi_br_sppr(sppr) | reg::spi_br_spr(spr));
+        CLOCK_SPEED / divisor
+    }
+
+    /// Change the mode, bit order, frame size and clock rate in one go.
+    /// The module is briefly disabled while we do this, so it must not be
+    /// in the middle of a transfer. Returns the clock rate chosen.
+    pub fn reconfigure(&mut self,
+                       mode: Mode,
+                       bit_order: BitOrder,
+                       frame_size: FrameSize,
+                       baud: u32)
+                       -> u32 {
+        self.reg.c1.modify(|x| x & !reg::SPI_C1_SPE);
+        let actual = self.set_baud(baud);
+        self.set_mode(mode);
+        self.set_bit_order(bit_order);
+        self.set_frame_size(frame_size);
+        self.reg.c1.modify(|x| x | reg::SPI_C1_SPE);
+        actual
+    }
+
+    /// Set the clock polarity and phase.
+    pub fn set_mode(&mut self, mode: Mode) {
+        let bits = match mode {
+            Mode::Mode0 => 0,
+            Mode::Mode1 => reg::SPI_C1_CPHA,
+            Mode::Mode2 => reg::SPI_C1_CPOL,
+            Mode::Mode3 => reg::SPI_C1_CPOL | reg::SPI_C1_CPHA,
+        };
+        self.reg.c1.modify(|x| (x & !(reg::SPI_C1_CPOL | reg::SPI_C1_CPHA)) | bits);
+    }
+
+    /// Set which bit goes on the wire first. For 16-bit frames this
+    /// also sets which octet goes first.
+    pub fn set_bit_order(&mut self, bit_order: BitOrder) {
+        self.bit_order = bit_order;
+        match bit_order {
+            BitOrder::MsbFirst => self.reg.c1.modify(|x| x & !reg::SPI_C1_LSBFE),
+            BitOrder::LsbFirst => self.reg.c1.modify(|x| x | reg::SPI_C1_LSBFE),
+        }
+    }
+
+    /// Set the frame size, which picks the transfer functions that work.
+    pub fn set_frame_size(&mut self, frame_size: FrameSize) {
+        self.frame_size = frame_size;
+    }
+
+    /// Get the frame size.
+    pub fn frame_size(&self) -> FrameSize {
+        self.frame_size
+    }
+
+    /// Send one octet and return the octet received at the same time.
+    pub fn exchange(&mut self, value: u8) -> Result<u8, SpiError> {
+        self.check_frame_size(FrameSize::Eight)?;
+        self.exchange_octet(value)
+    }
+
+    /// Send one 16-bit frame and return the frame received at the same
+    /// time.
+    pub fn exchange16(&mut self, value: u16) -> Result<u16, SpiError> {
+        self.check_frame_size(FrameSize::Sixteen)?;
+        match self.bit_order {
+            BitOrder::MsbFirst => {
+                let hi = self.exchange_octet((value >> 8) as u8)? as u16;
+                let lo = self.exchange_octet(value as u8)? as u16;
+                Ok((hi << 8) | lo)
+            }
+            BitOrder::LsbFirst => {
+                let lo = self.exchange_octet(value as u8)? as u16;
+                let hi = self.exchange_octet((value >> 8) as u8)? as u16;
+                Ok((hi << 8) | lo)
+            }
+        }
+    }
+
+    /// Full-duplex transfer of 8-bit frames. Each octet in `words` is
+    /// sent, and replaced with the octet received.
+    pub fn transfer(&mut self, words: &mut [u8]) -> Result<(), SpiError> {
+        self.check_frame_size(FrameSize::Eight)?;
+        for word in words.iter_mut() {
+            *word = self.exchange_octet(*word)?;
+        }
+        Ok(())
+    }
+
+    /// Send 8-bit frames, discarding whatever is received.
+    pub fn write(&mut self, words: &[u8]) -> Result<(), SpiError> {
+        self.check_frame_size(FrameSize::Eight)?;
+        for &word in words {
+            self.exchange_octet(word)?;
+        }
+        Ok(())
+    }
+
+    /// Full-duplex transfer of 16-bit frames. Each frame in `words` is
+    /// sent, and replaced with the frame received.
+    pub fn transfer16(&mut self, words: &mut [u16]) -> Result<(), SpiError> {
+        for word in words.iter_mut() {
+            *word = self.exchange16(*word)?;
+        }
+        Ok(())
+    }
+
+    /// Send 16-bit frames, discarding whatever is received.
+    pub fn write16(&mut self, words: &[u16]) -> Result<(), SpiError> {
+        for &word in words {
+            self.exchange16(word)?;
+        }
+        Ok(())
+    }
+
+    /// Check a transfer matches the frame size.
+    fn check_frame_size(&self, frame_size: FrameSize) -> Result<(), SpiError> {
+        if self.frame_size == frame_size {
+            Ok(())
+        } else {
+            Err(SpiError::WrongFrameSize)
+        }
+    }
+
+    /// Send one octet and return the octet received at the same time,
+    /// whatever the frame size.
+    fn exchange_octet(&mut self, value: u8) -> Result<u8, SpiError> {
+        while (self.reg.s.read() & reg::SPI_S_SPTEF) == 0 {
+            if self.mode_fault() {
+                return Err(SpiError::ModeFault);
+            }
+            nop();
+        }
+        self.reg.d.write(value);
+        while (self.reg.s.read() & reg::SPI_S_SPRF) == 0 {
+            if self.mode_fault() {
+                return Err(SpiError::ModeFault);
+            }
+            nop();
+        }
+        Ok(self.reg.d.read())
+    }
+
+    /// Clear the match flag. It's cleared by reading S with SPMF set,
+    /// then writing a one to SPMF.
+    fn clear_match(&mut self) {
+        let _ = self.reg.s.read();
+        self.reg.s.write(reg::SPI_S_SPMF);
+    }
+
+    /// Enable the clock to the module and stop it, ready to configure.
+    fn power_up(&mut self) {
+        // Enable the SPI peripheral
+        reg::get_sim().scgc.modify(|x| {
+            x |
+            match self.id {
+                SpiId::Spi0 => reg::SIM_SCGC_SPI0,
+                SpiId::Spi1 => reg::SIM_SCGC_SPI1,
+            }
+        });
+
+        // Stop it while we configure it
+        self.reg.c1.write(0);
+        self.reg.c2.write(0);
+    }
+}
+
+/// Must be called when the master releases SS at the end of a slave
+/// transaction - typically from a pin interrupt on the SS pin's rising
+/// edge. Calls the slave callback, and resets ready for the next
+/// transaction.
+pub fn slave_deselected(id: SpiId) {
+    let spi = get_spi_registers(id);
+    // Make sure the SPI interrupt doesn't fire while we're in here
+    let c1 = spi.c1.read();
+    spi.c1.write(c1 & !reg::SPI_C1_SPIE);
+    let state = unsafe { &mut SLAVE_STATE[id as usize] };
+    if let Some(ref mut state) = *state {
+        let len = if state.count < state.rx.len() {
+            state.count
+        } else {
+            state.rx.len()
+        };
+        (state.callback)(id, &state.rx[..len], state.count);
+        state.count = 0;
+        // A partly-sent octet may be sitting in the data register. The
+        // only way to flush it is to reset the module.
+        spi.c1.write(c1 & !(reg::SPI_C1_SPE | reg::SPI_C1_SPIE));
+        spi.c1.write(c1 & !reg::SPI_C1_SPIE);
+        spi.d.write(state.tx.first().cloned().unwrap_or(SLAVE_FILL));
+    }
+    spi.c1.write(c1);
+}
+
+/// Called when SPI 0 interrupt fires
+pub unsafe extern "C" fn spi0_isr() {
+    service_match(SpiId::Spi0);
+    service_slave(SpiId::Spi0);
+}
+
+/// Called when SPI 1 interrupt fires
+pub unsafe extern "C" fn spi1_isr() {
+    service_match(SpiId::Spi1);
+    service_slave(SpiId::Spi1);
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+/// Get a reference to the SPI control register struct in the chip.
+fn get_spi_registers(spi_id: SpiId) -> &'static mut reg::SpiRegisters {
+    match spi_id {
+        SpiId::Spi0 => reg::get_spi0(),
+        SpiId::Spi1 => reg::get_spi1(),
+    }
+}
+
+/// Clear the match flag, and tell whoever asked.
+unsafe fn service_match(id: SpiId) {
+    let spi = get_spi_registers(id);
+    if (spi.s.read() & reg::SPI_S_SPMF) != 0 {
+        spi.s.write(reg::SPI_S_SPMF);
+        if let Some(callback) = MATCH_CALLBACK[id as usize] {
+            callback(id);
+        }
+    }
+}
+
+/// Move one octet in and one octet out of the slave buffers. Does
+/// nothing unless we're a slave with buffers, so a master's received
+/// data is left for `exchange`.
+unsafe fn service_slave(id: SpiId) {
+    let spi = get_spi_registers(id);
+    if (spi.c1.read() & reg::SPI_C1_SPIE) == 0 {
+        return;
+    }
+    if let Some(ref mut state) = SLAVE_STATE[id as usize] {
+        if (spi.s.read() & reg::SPI_S_SPRF) == 0 {
+            return;
+        }
+        let value = spi.d.read();
+        if let Some(slot) = state.rx.get_mut(state.count) {
+            *slot = value;
+        }
+        state.count += 1;
+        if (spi.s.read() & reg::SPI_S_SPTEF) != 0 {
+            spi.d.write(state.tx.get(state.count).cloned().unwrap_or(SLAVE_FILL));
+        }
+    }
+}
+
+/// The SPI clock is the bus clock divided by (SPPR + 1) and by
+/// 2 ^ (SPR + 1). Find the smallest divisor which gives a clock no
+/// faster than `baud`. Returns (SPPR, SPR, divisor).
+fn calculate_divisor(baud: u32) -> (u8, u8, u32) {
+    let mut best = (7, 8, 8 * 512);
+    for spr in 0..9 {
+        for sppr in 0..8 {
+            let divisor = (sppr as u32 + 1) << (spr + 1);
+            if (CLOCK_SPEED / divisor) <= baud && divisor < best.2 {
+                best = (sppr, spr, divisor);
+            }
+        }
+    }
+    best
+}
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
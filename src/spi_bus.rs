@@ -0,0 +1,200 @@
+//! # Shared SPI bus for the KE06Z
+//!
+//! Lets several devices, each with their own GPIO chip select and their
+//! own SPI settings, share one SPI module. Each transaction is run as a
+//! closure - chip select is asserted before the closure runs and released
+//! after it returns, whether it succeeded or not. The closure gets a
+//! `Transfer`, which can move data but can't change the SPI settings.
+//!
+//! ```ignore
+//! let mut bus = SpiBus::new(Spi::new(SpiId::Spi0, ...));
+//! let flash = SpiDevice::new(PinPort::PortB(Pin::Pin5), Mode::Mode0,
+//!                            BitOrder::MsbFirst, FrameSize::Eight, 10_000_000);
+//! let mut id = [0x9F, 0, 0, 0];
+//! bus.transaction(&flash, |spi| spi.transfer(&mut id))?;
+//! ```
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use super::gpio::{self, Level, PinMode, PinPort};
+use super::spi::{BitOrder, FrameSize, Mode, Spi, SpiError};
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+/// A device on a shared SPI bus. Owns the (active low) chip select pin.
+pub struct SpiDevice {
+    cs: PinPort,
+    config: Config,
+}
+
+/// An SPI module shared between several `SpiDevice`s.
+pub struct SpiBus {
+    spi: Spi,
+    /// The settings the SPI module currently has, if known
+    current: Option<Config>,
+}
+
+/// The SPI module, as lent to a transaction. Only the data transfer
+/// functions are available, so the settings we cached can't change.
+pub struct Transfer<'a> {
+    spi: &'a mut Spi,
+}
+
+// ****************************************************************************
+//
+// Public Data
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// Private Types
+//
+// ****************************************************************************
+
+/// The per-device SPI settings
+#[derive(PartialEq, Clone, Copy)]
+struct Config {
+    mode: Mode,
+    bit_order: BitOrder,
+    frame_size: FrameSize,
+    baud: u32,
+}
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+impl SpiDevice {
+    /// Create a new device. The chip select pin is made an output and
+    /// driven high (inactive).
+    pub fn new(cs: PinPort,
+               mode: Mode,
+               bit_order: BitOrder,
+               frame_size: FrameSize,
+               baud: u32)
+               -> SpiDevice {
+        gpio::set_direction(cs, PinMode::Output);
+        gpio::set(cs, Level::High);
+        SpiDevice {
+            cs: cs,
+            config: Config {
+                mode: mode,
+                bit_order: bit_order,
+                frame_size: frame_size,
+                baud: baud,
+            },
+        }
+    }
+
+    /// Give up the device, handing back the chip select pin.
+    pub fn free(self) -> PinPort {
+        self.cs
+    }
+}
+
+impl SpiBus {
+    /// Take ownership of an SPI master so it can be shared.
+    pub fn new(spi: Spi) -> SpiBus {
+        SpiBus {
+            spi: spi,
+            current: None,
+        }
+    }
+
+    /// Run a transaction with `device`. The SPI module is set up with the
+    /// device's settings, chip select is asserted, and `f` is called to
+    /// do the transfers. Chip select is always released before this
+    /// returns, even if `f` returns an error.
+    pub fn transaction<T, E, F>(&mut self, device: &SpiDevice, f: F) -> Result<T, E>
+        where F: FnOnce(&mut Transfer) -> Result<T, E>
+    {
+        if self.current != Some(device.config) {
+            self.spi.reconfigure(device.config.mode,
+                                 device.config.bit_order,
+                                 device.config.frame_size,
+                                 device.config.baud);
+            self.current = Some(device.config);
+        }
+        gpio::set(device.cs, Level::Low);
+        let result = f(&mut Transfer { spi: &mut self.spi });
+        gpio::set(device.cs, Level::High);
+        result
+    }
+
+    /// Stop sharing the SPI module and hand it back.
+    pub fn free(self) -> Spi {
+        self.spi
+    }
+}
+
+impl<'a> Transfer<'a> {
+    /// See `Spi::exchange`.
+    pub fn exchange(&mut self, value: u8) -> Result<u8, SpiError> {
+        self.spi.exchange(value)
+    }
+
+    /// See `Spi::exchange16`.
+    pub fn exchange16(&mut self, value: u16) -> Result<u16, SpiError> {
+        self.spi.exchange16(value)
+    }
+
+    /// See `Spi::transfer`.
+    pub fn transfer(&mut self, words: &mut [u8]) -> Result<(), SpiError> {
+        self.spi.transfer(words)
+    }
+
+    /// See `Spi::write`.
+    pub fn write(&mut self, words: &[u8]) -> Result<(), SpiError> {
+        self.spi.write(words)
+    }
+
+    /// See `Spi::transfer16`.
+    pub fn transfer16(&mut self, words: &mut [u16]) -> Result<(), SpiError> {
+        self.spi.transfer16(words)
+    }
+
+    /// See `Spi::write16`.
+    pub fn write16(&mut self, words: &[u16]) -> Result<(), SpiError> {
+        self.spi.write16(words)
+    }
+
+    /// See `Spi::mode_fault`.
+    pub fn mode_fault(&self) -> bool {
+        self.spi.mode_fault()
+    }
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+// None
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
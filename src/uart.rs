@@ -8,8 +8,14 @@
 
 use core::fmt;
 use cortex_m::asm::nop;
-use embedded_serial::{BlockingTx, NonBlockingRx};
+use cortex_m::interrupt;
+use cortex_m::peripheral as cm_periph;
+use embedded_serial::{BlockingTx, NonBlockingTx};
+use embedded_serial::{BlockingRx, BlockingRxWithTimeout, NonBlockingRx};
 
+use super::cortex_m0::systick;
+use super::gpio::{self, Level, PinMode, PinPort};
+use super::power;
 use super::registers as reg;
 
 // ****************************************************************************
@@ -30,7 +36,10 @@ pub enum UartId {
 /// Controls a single UART
 /// Only supports 8/N/1 - who needs anything else?
 pub struct Uart {
+    id: UartId,
     nl_mode: NewlineMode,
+    rs485: Option<Rs485>,
+    multidrop: Option<Multidrop>,
     reg: &'static mut reg::UartRegisters,
 }
 
@@ -44,13 +53,92 @@ pub enum NewlineMode {
     SwapLFtoCRLF,
 }
 
+/// The length of the break character generated by `Uart::send_break`.
+#[derive(PartialEq, Clone, Copy)]
+pub enum BreakLength {
+    /// 10 bit times
+    Short,
+    /// 13 bit times, as required by LIN
+    Long,
+}
+
+/// How a sleeping receiver is woken in multidrop mode.
+#[derive(PartialEq, Clone, Copy)]
+pub enum WakeMethod {
+    /// 8 data bits. The receiver wakes after an idle line, and the first
+    /// character after the idle period is the address.
+    IdleLine,
+    /// 9 data bits. The receiver wakes on a character with the ninth bit
+    /// set, which is the address.
+    AddressMark,
+}
+
+/// When the receiver starts counting idle bit times.
+#[derive(PartialEq, Clone, Copy)]
+pub enum IdleLineType {
+    /// Count from the start bit, so the stop bit and any trailing ones
+    /// in the last character count towards the idle time
+    AfterStartBit,
+    /// Count from the stop bit, for more reliable detection
+    AfterStopBit,
+}
+
+/// How the UART transmitter and receiver are connected.
+#[derive(PartialEq, Clone, Copy)]
+pub enum LoopMode {
+    /// Separate RxD and TxD pins
+    Normal,
+    /// The transmitter is internally connected to the receiver and
+    /// the RxD pin is not used. Useful for self-test.
+    Loopback,
+    /// The TxD pin is used for both directions (half-duplex). The RxD
+    /// pin is not used. See `Uart::set_single_wire_direction`.
+    SingleWire,
+}
+
+/// The direction of the TxD pin in `LoopMode::SingleWire`.
+#[derive(PartialEq, Clone, Copy)]
+pub enum SingleWireDirection {
+    /// TxD is an input and we can receive
+    Receive,
+    /// TxD is an output and we can transmit
+    Transmit,
+}
+
+/// Reasons `Uart::autobaud` can fail
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AutobaudError {
+    /// No sync character arrived in time
+    Timeout,
+    /// The edges seen didn't look like a 0x55 sync character
+    BadSync,
+    /// The measured rate is too slow or too fast for the UART
+    OutOfRange,
+}
+
 // ****************************************************************************
 //
 // Private Types
 //
 // ****************************************************************************
 
-// None
+/// Multidrop (receiver wake-up) configuration for a Uart
+struct Multidrop {
+    /// Our node address
+    address: u8,
+    /// How the receiver is woken
+    wake: WakeMethod,
+    /// The next character woken up for is an address
+    awaiting_address: bool,
+}
+
+/// RS-485 driver-enable configuration for a Uart
+struct Rs485 {
+    /// The GPIO driving the transceiver's DE and /RE pins
+    de_pin: PinPort,
+    /// Release DE from the TC interrupt rather than in `flush`
+    use_interrupt: bool,
+}
 
 // ****************************************************************************
 //
@@ -70,6 +158,13 @@ pub enum NewlineMode {
 /// We set the internal clock to 20 MHz
 const CLOCK_SPEED: u32 = 20_000_000;
 
+/// The NVIC interrupt number of each UART
+const UART_IRQ: [u32; 3] = [12, 13, 14];
+
+/// `autobaud` snaps to one of these if it's within 4%
+const STANDARD_BAUDS: [u32; 11] = [1200, 2400, 4800, 9600, 14400, 19200, 38400, 57600,
+                                   76800, 115200, 230400];
+
 // ****************************************************************************
 //
 // Public Functions
@@ -85,7 +180,10 @@ impl Uart {
     /// - this will cause writeln!() to emit a CRLF.
     pub fn new(id: UartId, baud: u32, nl_mode: NewlineMode) -> Uart {
         let mut uart = Uart {
+            id: id,
             nl_mode: nl_mode,
+            rs485: None,
+            multidrop: None,
             reg: get_uart_registers(id),
         };
 
@@ -106,15 +204,563 @@ impl Uart {
         uart.reg.c1.write(0);
 
         // Set the baud rate
-        let baud_div = ((CLOCK_SPEED >> 4) + (baud / 2)) / baud;
-        uart.reg.bdh.write((baud_div >> 8) as u8);
-        uart.reg.bdl.write((baud_div & 0xFF) as u8);
+        uart.set_baud(baud);
 
         // Turn the receiver and transmitter back on
         uart.reg.c2.modify(|x| x | (reg::UART_C2_TE | reg::UART_C2_RE));
 
         uart
     }
+
+    /// Change the baud rate.
+    pub fn set_baud(&mut self, baud: u32) {
+        let baud_div = ((CLOCK_SPEED >> 4) + (baud / 2)) / baud;
+        let sbr_high = (baud_div >> 8) as u8 & reg::UART_BDH_SBR;
+        self.reg.bdh.modify(|x| (x & !reg::UART_BDH_SBR) | sbr_high);
+        // Writing BDL latches the new rate
+        self.reg.bdl.write((baud_div & 0xFF) as u8);
+    }
+
+    /// Measure the baud rate of the remote end, and switch to it.
+    ///
+    /// The remote end must send a 0x55 sync character, which has a
+    /// falling edge every two bit times. We time the five falling edges
+    /// on RxD with SysTick and, if the result is within 4% of a standard
+    /// rate, we round to that. Waits up to `timeout` SysTick ticks for the
+    /// first edge. Returns the new baud rate.
+    ///
+    /// Rates up to 115200 can be measured reliably. Interrupts should be
+    /// disabled while this runs, as they will spoil the timing. SysTick
+    /// must be running (see `systick::init`), but its interrupt isn't
+    /// needed.
+    pub fn autobaud(&mut self, timeout: usize) -> Result<u32, AutobaudError> {
+        // We poll the edge flag, so don't let the interrupt clear it
+        let bdh = self.reg.bdh.read();
+        self.reg.bdh.modify(|x| x & !reg::UART_BDH_RXEDGIE);
+        let result = self.measure_sync(timeout);
+        self.reg.bdh.modify(|x| x | (bdh & reg::UART_BDH_RXEDGIE));
+        // Whatever we received while measuring is garbage. Reading S1
+        // then D clears any error flags too.
+        while (self.reg.s1.read() & (reg::UART_S1_RDRF | reg::UART_S1_OR | reg::UART_S1_FE)) != 0 {
+            let _ = self.reg.data.read();
+        }
+        let ticks = result?;
+        // Eight bit times, rounded
+        let baud = ((systick::CLOCK_HZ as u64 * 8) + (ticks / 2)) / ticks;
+        let baud = baud as u32;
+        // The divider is 13 bits and must be at least 1
+        let min = (CLOCK_SPEED >> 4) / 8191;
+        let max = CLOCK_SPEED >> 4;
+        if baud < min || baud > max {
+            return Err(AutobaudError::OutOfRange);
+        }
+        let baud = STANDARD_BAUDS.iter()
+            .cloned()
+            .find(|&rate| baud > (rate - rate / 25) && baud < (rate + rate / 25))
+            .unwrap_or(baud);
+        self.set_baud(baud);
+        Ok(baud)
+    }
+
+    /// Wait for five falling edges on RxD and return the number of
+    /// SysTick ticks between the first and last.
+    fn measure_sync(&mut self, timeout: usize) -> Result<u64, AutobaudError> {
+        let mut edges = [0u64; 5];
+        // Ticks since we started. We count SysTick wraps ourselves, as
+        // the SysTick interrupt may be masked.
+        let mut now = 0u64;
+        let mut last = systick::get_ticks();
+        self.modify_s2(reg::UART_S2_RXEDGIF, 0);
+        for (idx, edge) in edges.iter_mut().enumerate() {
+            loop {
+                now += ticks_since(&mut last);
+                if (self.reg.s2.read() & reg::UART_S2_RXEDGIF) != 0 {
+                    *edge = now;
+                    break;
+                }
+                let limit = if idx == 0 {
+                    timeout as u64
+                } else {
+                    // A sync character can't take this long
+                    timeout as u64 + (systick::CLOCK_HZ / 50) as u64
+                };
+                if now >= limit {
+                    return Err(AutobaudError::Timeout);
+                }
+            }
+            self.modify_s2(reg::UART_S2_RXEDGIF, 0);
+        }
+        let total = edges[4] - edges[0];
+        if total == 0 {
+            return Err(AutobaudError::BadSync);
+        }
+        // Each gap should be a quarter of the total, give or take 25%
+        let quarter = total / 4;
+        for pair in edges.windows(2) {
+            let gap = pair[1] - pair[0];
+            if gap < (quarter - quarter / 4) || gap > (quarter + quarter / 4) {
+                return Err(AutobaudError::BadSync);
+            }
+        }
+        Ok(total)
+    }
+
+    /// Queue a single break character for transmission. The break is
+    /// sent once any data already in the transmitter has gone.
+    pub fn send_break(&mut self, length: BreakLength) {
+        match length {
+            BreakLength::Short => self.modify_s2(0, reg::UART_S2_BRK13),
+            BreakLength::Long => self.modify_s2(reg::UART_S2_BRK13, 0),
+        }
+        while (self.reg.s1.read() & reg::UART_S1_TDRE) == 0 {
+            nop();
+        }
+        // Toggling SBK queues exactly one break character
+        self.reg.c2.modify(|x| x | reg::UART_C2_SBK);
+        self.reg.c2.modify(|x| x & !reg::UART_C2_SBK);
+    }
+
+    /// Enable LIN break detection. While enabled, a break of 11 bit times
+    /// or more sets the flag checked by `lin_break_detected` instead of
+    /// being received as a zero octet. If `interrupt` is true, the UART
+    /// interrupt will also fire when a break is detected.
+    ///
+    /// Note that while break detection is armed, nothing else is
+    /// received - see `lin_break_detected`.
+    pub fn enable_lin_break_detect(&mut self, interrupt: bool) {
+        self.rearm_lin_break_detect();
+        if interrupt {
+            self.reg.bdh.modify(|x| x | reg::UART_BDH_LBKDIE);
+        } else {
+            self.reg.bdh.modify(|x| x & !reg::UART_BDH_LBKDIE);
+        }
+    }
+
+    /// Disable LIN break detection and its interrupt.
+    pub fn disable_lin_break_detect(&mut self) {
+        self.reg.bdh.modify(|x| x & !reg::UART_BDH_LBKDIE);
+        self.modify_s2(0, reg::UART_S2_LBKDE);
+    }
+
+    /// Returns true if a LIN break has been detected since the last call,
+    /// clearing the flag. Can be polled, or called from the UART interrupt
+    /// handler.
+    ///
+    /// The UART can't receive data while break detection is armed, so
+    /// once a break is seen, detection is disarmed until
+    /// `rearm_lin_break_detect` is called at the end of the frame.
+    pub fn lin_break_detected(&mut self) -> bool {
+        if (self.reg.s2.read() & reg::UART_S2_LBKDIF) != 0 {
+            self.modify_s2(reg::UART_S2_LBKDIF, reg::UART_S2_LBKDE);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Look for the next LIN break, dropping any stale break flag. The
+    /// interrupt setting from `enable_lin_break_detect` is kept.
+    pub fn rearm_lin_break_detect(&mut self) {
+        self.modify_s2(reg::UART_S2_LBKDIF | reg::UART_S2_LBKDE, 0);
+    }
+
+    /// Set and clear bits in S2. The two flag bits in S2 are cleared
+    /// by writing a one, so we take care to only write a one to a flag
+    /// if it's in `set`.
+    fn modify_s2(&mut self, set: u8, clear: u8) {
+        const FLAGS: u8 = reg::UART_S2_LBKDIF | reg::UART_S2_RXEDGIF;
+        self.reg.s2.modify(|x| ((x & !FLAGS & !clear) | set));
+    }
+
+    /// Select normal, internal loopback or single-wire mode. The
+    /// transmitter and receiver are briefly disabled while we switch.
+    /// Single-wire mode starts in the `Receive` direction.
+    pub fn set_loop_mode(&mut self, mode: LoopMode) {
+        let c2 = self.reg.c2.read();
+        self.reg.c2.modify(|x| x & !(reg::UART_C2_TE | reg::UART_C2_RE));
+        self.reg.c3.modify(|x| x & !reg::UART_C3_TXDIR);
+        self.reg.c1.modify(|x| {
+            let x = x & !(reg::UART_C1_LOOPS | reg::UART_C1_RSRC);
+            match mode {
+                LoopMode::Normal => x,
+                LoopMode::Loopback => x | reg::UART_C1_LOOPS,
+                LoopMode::SingleWire => x | reg::UART_C1_LOOPS | reg::UART_C1_RSRC,
+            }
+        });
+        self.reg.c2.write(c2);
+    }
+
+    /// In single-wire mode, turn the TxD pin around. When switching to
+    /// `Receive`, we first wait for any data in the transmitter to
+    /// finish going out, so the last octet isn't cut short.
+    pub fn set_single_wire_direction(&mut self, direction: SingleWireDirection) {
+        match direction {
+            SingleWireDirection::Transmit => {
+                self.reg.c3.modify(|x| x | reg::UART_C3_TXDIR);
+            }
+            SingleWireDirection::Receive => {
+                while (self.reg.s1.read() & reg::UART_S1_TC) == 0 {
+                    nop();
+                }
+                self.reg.c3.modify(|x| x & !reg::UART_C3_TXDIR);
+            }
+        }
+    }
+
+    /// Check the UART can talk to itself, using internal loopback. Sends
+    /// a few test octets and checks they come back, waiting up to
+    /// `timeout` SysTick ticks for each. The previous loop mode is
+    /// restored afterwards. The RS-485 DE pin (if any) is left released,
+    /// so the test pattern never reaches the bus.
+    pub fn self_test(&mut self, timeout: usize) -> bool {
+        const PATTERN: [u8; 4] = [0x55, 0xAA, 0x00, 0xFF];
+        // Let anything in progress go, and keep DE off during the test
+        self.flush();
+        let rs485 = self.rs485.take();
+        let c1 = self.reg.c1.read();
+        let c3 = self.reg.c3.read();
+        self.set_loop_mode(LoopMode::Loopback);
+        // Drop anything stale sitting in the receiver
+        while self.getc_try().is_ok() {}
+        let mut result = true;
+        for &b in PATTERN.iter() {
+            let ok = self.wait_s1(reg::UART_S1_TDRE, timeout) && {
+                self.write_data(b);
+                BlockingRxWithTimeout::getc(self, timeout) == Ok(b)
+            };
+            if !ok {
+                result = false;
+                break;
+            }
+        }
+        // Put the loop mode back as we found it
+        let c2 = self.reg.c2.read();
+        self.reg.c2.modify(|x| x & !(reg::UART_C2_TE | reg::UART_C2_RE));
+        self.reg.c1.write(c1);
+        self.reg.c3.write(c3);
+        self.reg.c2.write(c2);
+        self.rs485 = rs485;
+        result
+    }
+
+    /// Invert the polarity of received data, so the idle line is low.
+    /// Useful for SBUS style receivers.
+    pub fn set_rx_inverted(&mut self, inverted: bool) {
+        if inverted {
+            self.modify_s2(reg::UART_S2_RXINV, 0);
+        } else {
+            self.modify_s2(0, reg::UART_S2_RXINV);
+        }
+    }
+
+    /// Invert the polarity of transmitted data, so the idle line is low.
+    pub fn set_tx_inverted(&mut self, inverted: bool) {
+        if inverted {
+            self.reg.c3.modify(|x| x | reg::UART_C3_TXINV);
+        } else {
+            self.reg.c3.modify(|x| x & !reg::UART_C3_TXINV);
+        }
+    }
+
+    /// Configure idle-line detection. The idle flag is set when the
+    /// line has been idle for a whole character time after at least one
+    /// character has been received - i.e. at the end of a packet. If
+    /// `interrupt` is true, the UART interrupt will also fire.
+    pub fn enable_idle_detect(&mut self, line_type: IdleLineType, interrupt: bool) {
+        match line_type {
+            IdleLineType::AfterStartBit => self.reg.c1.modify(|x| x & !reg::UART_C1_ILT),
+            IdleLineType::AfterStopBit => self.reg.c1.modify(|x| x | reg::UART_C1_ILT),
+        }
+        if interrupt {
+            self.reg.c2.modify(|x| x | reg::UART_C2_ILIE);
+        } else {
+            self.reg.c2.modify(|x| x & !reg::UART_C2_ILIE);
+        }
+    }
+
+    /// Stop the idle line interrupt.
+    pub fn disable_idle_detect(&mut self) {
+        self.reg.c2.modify(|x| x & !reg::UART_C2_ILIE);
+    }
+
+    /// Returns true if the line has gone idle since the last call,
+    /// clearing the flag. Can be polled, or called from the UART
+    /// interrupt handler.
+    ///
+    /// The flag is cleared by reading the data register, so if there is
+    /// received data waiting, we leave the flag alone and return false.
+    /// Read the data first and then call this again.
+    pub fn idle_detected(&mut self) -> bool {
+        // Nothing else may read S1 between our S1 and D reads
+        unsafe {
+            interrupt::free(|| {
+                let s1 = self.reg.s1.read();
+                if (s1 & reg::UART_S1_IDLE) != 0 && (s1 & reg::UART_S1_RDRF) == 0 {
+                    // Reading S1 then D clears IDLE. An octet arriving
+                    // after our S1 read keeps RDRF set, as S1 wasn't read
+                    // with RDRF set, so it stays in D for the RX path.
+                    let _ = self.reg.data.read();
+                    true
+                } else {
+                    false
+                }
+            })
+        }
+    }
+
+    /// Join a multidrop network as node `address`. The receiver is put
+    /// to sleep, and the hardware ignores all traffic until it is woken
+    /// by an address. Use `multidrop_getc_try` to receive - it checks
+    /// the address, and puts the receiver back to sleep if the following
+    /// data is for some other node.
+    ///
+    /// `WakeMethod::AddressMark` switches the UART to 9 data bits.
+    ///
+    /// In `WakeMethod::IdleLine` mode, `multidrop_getc_try` treats an idle
+    /// line as the end of the packet and puts the receiver back to sleep,
+    /// so the next character is taken as an address. It must be polled
+    /// at least once a character time to see the idle line before that
+    /// address arrives. If that can't be guaranteed, call
+    /// `sleep_receiver` yourself when a packet is over.
+    pub fn enable_multidrop(&mut self, address: u8, wake: WakeMethod) {
+        match wake {
+            WakeMethod::IdleLine => {
+                self.reg.c1.modify(|x| x & !(reg::UART_C1_M | reg::UART_C1_WAKE));
+            }
+            WakeMethod::AddressMark => {
+                self.reg.c1.modify(|x| x | reg::UART_C1_M | reg::UART_C1_WAKE);
+            }
+        }
+        // Don't flag idle lines while we're asleep
+        self.modify_s2(0, reg::UART_S2_RWUID);
+        self.multidrop = Some(Multidrop {
+            address: address,
+            wake: wake,
+            awaiting_address: false,
+        });
+        self.sleep_receiver();
+    }
+
+    /// Leave the multidrop network. The receiver is woken and the UART
+    /// returns to 8 data bits.
+    pub fn disable_multidrop(&mut self) {
+        self.reg.c2.modify(|x| x & !reg::UART_C2_RWU);
+        self.reg.c1.modify(|x| x & !(reg::UART_C1_M | reg::UART_C1_WAKE));
+        self.reg.c3.modify(|x| x & !reg::UART_C3_T8);
+        self.modify_s2(0, reg::UART_S2_RWUID);
+        self.multidrop = None;
+    }
+
+    /// Put the receiver to sleep until the next address arrives.
+    pub fn sleep_receiver(&mut self) {
+        if let Some(ref mut md) = self.multidrop {
+            md.awaiting_address = true;
+        }
+        self.reg.c2.modify(|x| x | reg::UART_C2_RWU);
+    }
+
+    /// Returns true if the receiver is asleep waiting for an address.
+    pub fn receiver_asleep(&self) -> bool {
+        (self.reg.c2.read() & reg::UART_C2_RWU) != 0
+    }
+
+    /// Attempts to read data addressed to this node. Returns `Err(())`
+    /// if there is no data, or if the character received was an address
+    /// (which is consumed). If the address was not ours, the receiver is
+    /// put back to sleep.
+    pub fn multidrop_getc_try(&mut self) -> Result<u8, ()> {
+        if (self.reg.s1.read() & reg::UART_S1_RDRF) == 0 {
+            let packet_over = match self.multidrop {
+                Some(ref md) => md.wake == WakeMethod::IdleLine && !md.awaiting_address,
+                None => false,
+            };
+            if packet_over && !self.receiver_asleep() && self.idle_detected() {
+                self.sleep_receiver();
+            }
+            return Err(());
+        }
+        // R8 must be read before the data register
+        let r8 = (self.reg.c3.read() & reg::UART_C3_R8) != 0;
+        let value = self.reg.data.read();
+        let (is_address, ours) = match self.multidrop {
+            Some(ref mut md) => {
+                let is_address = match md.wake {
+                    WakeMethod::IdleLine => md.awaiting_address,
+                    WakeMethod::AddressMark => r8,
+                };
+                md.awaiting_address = false;
+                (is_address, value == md.address)
+            }
+            None => (false, true),
+        };
+        if !is_address {
+            Ok(value)
+        } else {
+            if !ours {
+                self.sleep_receiver();
+            }
+            Err(())
+        }
+    }
+
+    /// Send an address to the multidrop network, waking every node. In
+    /// `WakeMethod::AddressMark` mode this sets the ninth bit. In
+    /// `WakeMethod::IdleLine` mode the caller must have left the line
+    /// idle for a character time first (e.g. with `flush`).
+    pub fn send_address(&mut self, address: u8) {
+        while (self.reg.s1.read() & reg::UART_S1_TDRE) == 0 {
+            nop();
+        }
+        self.reg.c3.modify(|x| x | reg::UART_C3_T8);
+        self.write_data(address);
+        // T8 goes to the shifter along with the data, so wait for that
+        // before clearing it for the octets that follow.
+        while (self.reg.s1.read() & reg::UART_S1_TDRE) == 0 {
+            nop();
+        }
+        self.reg.c3.modify(|x| x & !reg::UART_C3_T8);
+    }
+
+    /// Arm the RxD active edge interrupt. A falling edge on RxD (i.e. the
+    /// start bit of an incoming character) fires the UART interrupt, even
+    /// in Stop mode, so this can be used with `power::stop` to wake the
+    /// chip when data arrives. The interrupt handler must call
+    /// `rx_edge_detected` to clear the flag.
+    ///
+    /// The character that causes the wake-up will be lost (or corrupted)
+    /// as the bus clock is not running when its start bit arrives.
+    pub fn enable_rx_edge_wakeup(&mut self) {
+        self.modify_s2(reg::UART_S2_RXEDGIF, 0);
+        self.reg.bdh.modify(|x| x | reg::UART_BDH_RXEDGIE);
+    }
+
+    /// Disarm the RxD active edge interrupt.
+    pub fn disable_rx_edge_wakeup(&mut self) {
+        self.reg.bdh.modify(|x| x & !reg::UART_BDH_RXEDGIE);
+        self.modify_s2(reg::UART_S2_RXEDGIF, 0);
+    }
+
+    /// Returns true if an active edge has been seen on RxD since the last
+    /// call, clearing the flag.
+    pub fn rx_edge_detected(&mut self) -> bool {
+        if (self.reg.s2.read() & reg::UART_S2_RXEDGIF) != 0 {
+            self.modify_s2(reg::UART_S2_RXEDGIF, 0);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Wait in Stop mode until an active edge on RxD. Any outbound data
+    /// is flushed first, and the clocks are running again (see
+    /// `power::stop`) by the time this returns.
+    ///
+    /// The UART interrupt must be enabled in the NVIC, but no handler
+    /// runs for the edge: interrupts are masked while we're stopped, so
+    /// the pending interrupt just wakes the core. The edge flag and the
+    /// pending interrupt are cleared here before they're unmasked again.
+    pub fn stop_until_rx(&mut self) {
+        self.flush();
+        let irq = UART_IRQ[self.id as usize];
+        unsafe {
+            interrupt::free(|| {
+                self.enable_rx_edge_wakeup();
+                power::stop();
+                self.disable_rx_edge_wakeup();
+                cm_periph::nvic_mut().icpr[0].write(1 << irq);
+            });
+        }
+    }
+
+    /// Drive an RS-485 transceiver. The given pin is made an output and
+    /// is owned by this Uart from now on. It is driven high (DE asserted)
+    /// before each octet is sent and driven low again once the
+    /// transmitter is completely idle.
+    ///
+    /// If `use_interrupt` is true, DE is released from the transmit
+    /// complete interrupt, so `handle_interrupt` must be called from this
+    /// UART's interrupt handler. Otherwise, every transmit path
+    /// (including `putc_try`) busy-waits for the octet to finish and
+    /// then releases DE, so octets go out one at a time.
+    pub fn enable_rs485(&mut self, de_pin: PinPort, use_interrupt: bool) {
+        gpio::set_direction(de_pin, PinMode::Output);
+        gpio::set(de_pin, Level::Low);
+        self.rs485 = Some(Rs485 {
+            de_pin: de_pin,
+            use_interrupt: use_interrupt,
+        });
+    }
+
+    /// Stop driving the RS-485 transceiver, handing back the DE pin.
+    /// Waits for any transmission in progress to finish first.
+    pub fn disable_rs485(&mut self) -> Option<PinPort> {
+        self.flush();
+        self.rs485.take().map(|rs485| rs485.de_pin)
+    }
+
+    /// Busy-wait until every queued octet has left the transmitter,
+    /// then release the RS-485 DE pin (if any).
+    pub fn flush(&mut self) {
+        while (self.reg.s1.read() & reg::UART_S1_TC) == 0 {
+            nop();
+        }
+        self.reg.c2.modify(|x| x & !reg::UART_C2_TCIE);
+        if let Some(ref rs485) = self.rs485 {
+            gpio::set(rs485.de_pin, Level::Low);
+        }
+    }
+
+    /// Should be called from this UART's interrupt handler. Releases the
+    /// RS-485 DE pin once transmission is complete.
+    pub fn handle_interrupt(&mut self) {
+        let tc = (self.reg.s1.read() & reg::UART_S1_TC) != 0;
+        let tcie = (self.reg.c2.read() & reg::UART_C2_TCIE) != 0;
+        if tc && tcie {
+            self.reg.c2.modify(|x| x & !reg::UART_C2_TCIE);
+            if let Some(ref rs485) = self.rs485 {
+                gpio::set(rs485.de_pin, Level::Low);
+            }
+        }
+    }
+
+    /// Load an octet into the transmitter, which must be empty. Asserts
+    /// the RS-485 DE pin first, if we have one, and arranges for it to be
+    /// released after TC.
+    fn write_data(&mut self, value: u8) {
+        let use_interrupt = match self.rs485 {
+            Some(ref rs485) => {
+                // TC from the previous octet mustn't release DE now
+                self.reg.c2.modify(|x| x & !reg::UART_C2_TCIE);
+                gpio::set(rs485.de_pin, Level::High);
+                Some(rs485.use_interrupt)
+            }
+            None => None,
+        };
+        self.reg.data.write(value);
+        match use_interrupt {
+            // Writing the data cleared TC, so this fires when the octet
+            // (and any that follow it) have gone.
+            Some(true) => self.reg.c2.modify(|x| x | reg::UART_C2_TCIE),
+            Some(false) => self.flush(),
+            None => {}
+        }
+    }
+
+    /// Busy-wait until the given bit(s) in S1 are set, or until
+    /// `timeout` SysTick ticks have elapsed. Returns `true` if the
+    /// bits were set in time.
+    ///
+    /// Requires `systick::init()` to have been called and
+    /// `systick::isr()` to be attached to the SysTick vector.
+    fn wait_s1(&mut self, mask: u8, timeout: usize) -> bool {
+        let start = systick::run_time_ticks();
+        while (self.reg.s1.read() & mask) == 0 {
+            if (systick::run_time_ticks() - start) >= (timeout as u64) {
+                return false;
+            }
+        }
+        true
+    }
 }
 
 impl BlockingTx for Uart {
@@ -127,11 +773,57 @@ impl BlockingTx for Uart {
         while (self.reg.s1.read() & reg::UART_S1_TDRE) == 0 {
             nop();
         }
-        self.reg.data.write(value);
+        self.write_data(value);
         Ok(())
     }
 }
 
+impl NonBlockingTx for Uart {
+    type Error = ();
+
+    /// Attempts to write to the UART. Returns `Err(())`
+    /// if the data register isn't empty, or `Ok(())`.
+    fn putc_try(&mut self, value: u8) -> Result<(), Self::Error> {
+        if (self.reg.s1.read() & reg::UART_S1_TDRE) == 0 {
+            Err(())
+        } else {
+            self.write_data(value);
+            Ok(())
+        }
+    }
+}
+
+impl BlockingRx for Uart {
+    type Error = ();
+
+    /// Read a single octet, busy-waiting until the data register
+    /// is full.
+    /// Never returns `Err`.
+    fn getc(&mut self) -> Result<u8, Self::Error> {
+        while (self.reg.s1.read() & reg::UART_S1_RDRF) == 0 {
+            nop();
+        }
+        Ok(self.reg.data.read())
+    }
+}
+
+impl BlockingRxWithTimeout for Uart {
+    /// Timeouts are measured in SysTick ticks.
+    type Timeout = usize;
+    type Error = ();
+
+    /// Read a single octet, busy-waiting until the data register
+    /// is full. Returns `Err(())` if nothing arrived within
+    /// `timeout` SysTick ticks.
+    fn getc(&mut self, timeout: Self::Timeout) -> Result<u8, Self::Error> {
+        if self.wait_s1(reg::UART_S1_RDRF, timeout) {
+            Ok(self.reg.data.read())
+        } else {
+            Err(())
+        }
+    }
+}
+
 impl NonBlockingRx for Uart {
     type Error = ();
 
@@ -187,6 +879,16 @@ fn get_uart_registers(uart_id: UartId) -> &'static mut reg::UartRegisters {
     }
 }
 
+/// The SysTick ticks since `last`, which is then updated. Doesn't need
+/// the SysTick interrupt, but must be called at least once per wrap.
+fn ticks_since(last: &mut usize) -> u64 {
+    let now = systick::get_ticks();
+    // SysTick counts down
+    let delta = last.wrapping_sub(now) & systick::SYSTICK_MAX;
+    *last = now;
+    delta as u64
+}
+
 // ****************************************************************************
 //
 // End Of File